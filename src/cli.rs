@@ -0,0 +1,32 @@
+use crate::storage;
+
+const USAGE: &str = "Usage:
+  xaudio-cli                              Start the player
+  xaudio-cli import <file> [playlist]     Import a .m3u8, .xspf or .json file into a playlist
+  xaudio-cli export <playlist> <file>     Export a playlist as .m3u8, .xspf or .json";
+
+/// Run a subcommand instead of the player. Returns the message to print, or
+/// an error with the usage when the arguments don't make sense.
+pub fn run_command(args: &[String]) -> Result<String, String> {
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+    match args.as_slice() {
+        ["import", file] | ["import", file, _] => {
+            let name = args.get(2).copied();
+            let (name, songs, added) = storage::import_playlist(&storage::expand_home(file), name)
+                .map_err(|e| format!("Cannot import {}: {}", file, e))?;
+            Ok(format!(
+                "Imported {} songs into playlist {} ({} songs)",
+                added,
+                name,
+                songs.len()
+            ))
+        }
+        ["export", name, file] => {
+            let count = storage::export_playlist(name, &storage::expand_home(file))
+                .map_err(|e| format!("Cannot export playlist {}: {}", name, e))?;
+            Ok(format!("Exported {} songs to {}", count, file))
+        }
+        ["help"] | ["--help"] | ["-h"] => Ok(USAGE.to_owned()),
+        _ => Err(USAGE.to_owned()),
+    }
+}
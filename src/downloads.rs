@@ -0,0 +1,229 @@
+use crate::{
+    extractor::run_extractor, storage::cache_dir, utils::unix_timestamp, youtube::watch_url,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+const DEFAULT_LIMIT_MB: u64 = 1024;
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+struct CachedSong {
+    /// Name of the file in the cache directory.
+    file: String,
+    size: u64,
+    /// Unix timestamp of when the song was downloaded or last played.
+    last_used: u64,
+}
+
+/// Audio files of Youtube songs downloaded with the extractor, to play them
+/// without a connection. When the files take more than the limit, the least
+/// recently used ones are deleted.
+pub struct DownloadCache {
+    dir: PathBuf,
+    limit: u64,
+    songs: HashMap<String, CachedSong>,
+}
+
+impl DownloadCache {
+    /// Open the cache in `$XDG_CACHE_HOME/xaudio/audio`, limited to
+    /// `XAUDIO_CACHE_LIMIT_MB` megabytes (1 GB by default).
+    pub fn open() -> Self {
+        let limit_mb = env::var("XAUDIO_CACHE_LIMIT_MB")
+            .ok()
+            .and_then(|limit| limit.trim().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_LIMIT_MB);
+        Self::open_in(cache_dir().join("audio"), limit_mb * 1024 * 1024)
+    }
+
+    /// Open the cache of `dir`, limited to `limit` bytes.
+    pub fn open_in(dir: PathBuf, limit: u64) -> Self {
+        let mut songs: HashMap<String, CachedSong> = fs::read_to_string(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        // files deleted by hand are not in the cache anymore
+        songs.retain(|_, song| dir.join(&song.file).is_file());
+        let mut cache = Self { dir, limit, songs };
+        if cache.evict(None) {
+            cache.save();
+        }
+        cache
+    }
+
+    pub fn ids(&self) -> HashSet<String> {
+        self.songs.keys().cloned().collect()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.songs.contains_key(id)
+    }
+
+    /// The downloaded file of the song, marked as just used. The index is not
+    /// written for that, it's saved with the next download or on quit.
+    pub fn cached_file(&mut self, id: &str) -> Option<String> {
+        let song = self.songs.get_mut(id)?;
+        let path = self.dir.join(&song.file);
+        if !path.is_file() {
+            self.songs.remove(id);
+            return None;
+        }
+        song.last_used = unix_timestamp();
+        path.to_str().map(str::to_owned)
+    }
+
+    fn add(&mut self, id: &str, file: String, size: u64) {
+        let song = CachedSong {
+            file,
+            size,
+            last_used: unix_timestamp(),
+        };
+        self.songs.insert(id.to_owned(), song);
+        self.evict(Some(id));
+        self.save();
+    }
+
+    /// Delete the least recently used files until the cache fits in its limit,
+    /// except the `keep` song. Returns whether any file was deleted.
+    fn evict(&mut self, keep: Option<&str>) -> bool {
+        let mut total = self.songs.values().map(|song| song.size).sum::<u64>();
+        let mut by_last_use = self
+            .songs
+            .iter()
+            .filter(|(id, _)| Some(id.as_str()) != keep)
+            .map(|(id, song)| (song.last_used, id.clone()))
+            .collect::<Vec<(u64, String)>>();
+        by_last_use.sort();
+        let mut evicted = false;
+        for (_, id) in by_last_use {
+            if total <= self.limit {
+                break;
+            }
+            if let Some(song) = self.songs.remove(&id) {
+                _ = fs::remove_file(self.dir.join(&song.file));
+                total -= song.size;
+                evicted = true;
+            }
+        }
+        evicted
+    }
+
+    pub fn save(&self) {
+        if let Ok(content) = serde_json::to_string_pretty(&self.songs) {
+            _ = fs::create_dir_all(&self.dir);
+            _ = fs::write(self.dir.join(INDEX_FILE), content);
+        }
+    }
+}
+
+/// Download the audio of a Youtube video into the cache. The lock is only
+/// taken before and after the download, so the songs can still be played.
+pub async fn download(cache: &Mutex<DownloadCache>, id: &str) -> Result<(), String> {
+    let dir = cache.lock().unwrap().dir.clone();
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let output = dir.join(format!("{}.%(ext)s", id));
+    run_extractor(&[
+        "-f",
+        "bestaudio/best",
+        "--no-playlist",
+        "--no-progress",
+        "-q",
+        "-o",
+        &output.to_string_lossy(),
+        &watch_url(id),
+    ])
+    .await?;
+    // the extension depends on the format that was picked
+    let (file, size) = fs::read_dir(&dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let (stem, extension) = name.rsplit_once('.')?;
+            (stem == id && extension != "part").then_some((name, entry.metadata().ok()?.len()))
+        })
+        .next()
+        .ok_or_else(|| "the downloaded file is missing".to_owned())?;
+    cache.lock().unwrap().add(id, file, size);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_with(name: &str, limit: u64, songs: &[(&str, u64, u64)]) -> DownloadCache {
+        let dir = env::temp_dir().join(format!("xaudio-{}-{}", name, std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let songs = songs
+            .iter()
+            .map(|&(id, size, last_used)| {
+                let file = format!("{}.webm", id);
+                fs::write(dir.join(&file), vec![0; size as usize]).unwrap();
+                let song = CachedSong {
+                    file,
+                    size,
+                    last_used,
+                };
+                (id.to_owned(), song)
+            })
+            .collect();
+        DownloadCache { dir, limit, songs }
+    }
+
+    fn sorted_ids(cache: &DownloadCache) -> Vec<String> {
+        let mut ids = cache.ids().into_iter().collect::<Vec<String>>();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn evict_deletes_the_least_recently_used_songs() {
+        let mut cache = cache_with(
+            "evict",
+            25,
+            &[("old", 10, 100), ("recent", 10, 300), ("middle", 10, 200)],
+        );
+        assert!(cache.evict(None));
+        assert_eq!(sorted_ids(&cache), ["middle", "recent"]);
+        assert!(!cache.dir.join("old.webm").exists());
+        assert!(cache.dir.join("middle.webm").exists());
+        assert!(!cache.evict(None));
+        _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn evict_keeps_the_new_song() {
+        let mut cache = cache_with(
+            "keep",
+            15,
+            &[("new", 10, 100), ("recent", 10, 300), ("middle", 10, 200)],
+        );
+        assert!(cache.evict(Some("new")));
+        assert_eq!(sorted_ids(&cache), ["new"]);
+        _ = fs::remove_dir_all(&cache.dir);
+    }
+
+    #[test]
+    fn playing_a_song_marks_it_as_used() {
+        let mut cache = cache_with("used", 25, &[("old", 10, 100), ("middle", 10, 200)]);
+        assert!(cache.cached_file("old").is_some());
+        cache.songs.insert(
+            "new".to_owned(),
+            CachedSong {
+                file: "new.webm".to_owned(),
+                size: 10,
+                last_used: unix_timestamp(),
+            },
+        );
+        cache.evict(Some("new"));
+        assert_eq!(sorted_ids(&cache), ["new", "old"]);
+        assert!(!cache.dir.join(INDEX_FILE).exists());
+        _ = fs::remove_dir_all(&cache.dir);
+    }
+}
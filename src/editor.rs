@@ -0,0 +1,184 @@
+use unicode_width::UnicodeWidthChar;
+
+/// Edits of the input line other than typing and backspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorAction {
+    Left,
+    Right,
+    Home,
+    End,
+    DeleteForward,
+    DeleteWord,
+    KillLine,
+}
+
+/// A single line of text with a cursor, used by the search box and prompts.
+/// The cursor is an index in `chars`, not a byte offset or a screen column.
+#[derive(Debug, Default, Clone)]
+pub struct LineEditor {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// Replace the text and put the cursor at the end.
+    pub fn set_text(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.set_text("");
+    }
+
+    pub fn cursor_at_end(&self) -> bool {
+        self.cursor == self.chars.len()
+    }
+
+    pub fn insert(&mut self, ch: char) {
+        // control characters (like a pasted tab or new line) would mess up
+        // the screen, they are typed as spaces
+        let ch = if ch.is_control() { ' ' } else { ch };
+        self.chars.insert(self.cursor, ch);
+        self.cursor += 1;
+    }
+
+    pub fn insert_str(&mut self, text: &str) {
+        text.chars().for_each(|ch| self.insert(ch));
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn apply(&mut self, action: EditorAction) {
+        match action {
+            EditorAction::Left => self.cursor = self.cursor.saturating_sub(1),
+            EditorAction::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            EditorAction::Home => self.cursor = 0,
+            EditorAction::End => self.cursor = self.chars.len(),
+            EditorAction::DeleteForward => {
+                if self.cursor < self.chars.len() {
+                    self.chars.remove(self.cursor);
+                }
+            }
+            EditorAction::DeleteWord => {
+                // like in a shell: the spaces before the cursor, then the word
+                let mut start = self.cursor;
+                while start > 0 && self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.chars.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            EditorAction::KillLine => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+        }
+    }
+
+    /// The part of the text that fits in `width` screen columns, scrolled so
+    /// the cursor is always visible: the text before the cursor, the character
+    /// under the cursor (a space at the end of the line) and the text after it.
+    pub fn view(&self, width: usize) -> (String, String, String) {
+        let cursor_char = self.chars.get(self.cursor).copied().unwrap_or(' ');
+        let mut remaining = width.saturating_sub(char_width(cursor_char));
+        let mut start = self.cursor;
+        while start > 0 && char_width(self.chars[start - 1]) <= remaining {
+            start -= 1;
+            remaining -= char_width(self.chars[start]);
+        }
+        let after_start = (self.cursor + 1).min(self.chars.len());
+        let mut end = after_start;
+        while end < self.chars.len() && char_width(self.chars[end]) <= remaining {
+            remaining -= char_width(self.chars[end]);
+            end += 1;
+        }
+        (
+            self.chars[start..self.cursor].iter().collect(),
+            cursor_char.to_string(),
+            self.chars[after_start..end].iter().collect(),
+        )
+    }
+}
+
+/// Number of screen columns taken by the character, 2 for most CJK characters
+/// and emoji.
+pub fn char_width(ch: char) -> usize {
+    ch.width().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(text: &str, cursor: usize) -> LineEditor {
+        let mut editor = LineEditor::default();
+        editor.set_text(text);
+        editor.cursor = cursor;
+        editor
+    }
+
+    #[test]
+    fn paste_inserts_at_the_cursor() {
+        let mut editor = editor("queen aid", 6);
+        editor.insert_str("live ");
+        assert_eq!(editor.text(), "queen live aid");
+        assert_eq!(editor.cursor, 11);
+    }
+
+    #[test]
+    fn delete_word_removes_the_spaces_then_the_word() {
+        let mut editor = editor("queen live  aid", 12);
+        editor.apply(EditorAction::DeleteWord);
+        assert_eq!(editor.text(), "queen aid");
+        assert_eq!(editor.cursor, 6);
+        editor.apply(EditorAction::DeleteWord);
+        assert_eq!(editor.text(), "aid");
+        editor.apply(EditorAction::DeleteWord);
+        assert_eq!(editor.text(), "aid");
+    }
+
+    #[test]
+    fn kill_line_keeps_the_text_after_the_cursor() {
+        let mut editor = editor("queen live aid", 11);
+        editor.apply(EditorAction::KillLine);
+        assert_eq!(editor.text(), "aid");
+        assert_eq!(editor.cursor, 0);
+    }
+
+    #[test]
+    fn backspace_and_delete_stop_at_the_ends() {
+        let mut editor = editor("ab", 0);
+        editor.backspace();
+        assert_eq!(editor.text(), "ab");
+        editor.apply(EditorAction::End);
+        editor.apply(EditorAction::DeleteForward);
+        assert_eq!(editor.text(), "ab");
+        editor.backspace();
+        assert_eq!(editor.text(), "a");
+    }
+
+    #[test]
+    fn view_scrolls_to_the_cursor() {
+        assert_eq!(
+            editor("abcdef", 6).view(4),
+            ("def".to_owned(), " ".to_owned(), String::new())
+        );
+        assert_eq!(
+            editor("日本語", 1).view(5),
+            ("日".to_owned(), "本".to_owned(), String::new())
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_EXTRACTOR: &str = "yt-dlp";
+// used when the stream URL doesn't say when it expires
+const DEFAULT_URL_LIFETIME: Duration = Duration::from_secs(60 * 60);
+// stop using a cached URL a bit before it expires, so it doesn't expire while
+// MPV is still buffering the song
+const EXPIRY_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// The program used to extract audio stream URLs (and to search, for the
+/// yt-dlp provider): `XAUDIO_EXTRACTOR`, yt-dlp by default. It has to accept
+/// the same options as yt-dlp, youtube-dl does.
+pub fn extractor_program() -> String {
+    env::var("XAUDIO_EXTRACTOR")
+        .ok()
+        .filter(|program| !program.trim().is_empty())
+        .unwrap_or(DEFAULT_EXTRACTOR.to_owned())
+}
+
+/// Run the extractor and return what it printed.
+pub async fn run_extractor(args: &[&str]) -> Result<String, String> {
+    let program = extractor_program();
+    let output = tokio::process::Command::new(&program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("Cannot run {}: {}", program, e))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        let error = error.lines().last().unwrap_or_default().trim();
+        return Err(format!("{} failed: {}", program, error));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Resolves page URLs (like a Youtube video) to audio stream URLs, and keeps
+/// them until they expire so playing a song again doesn't run the extractor.
+#[derive(Default)]
+pub struct Extractor {
+    cache: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl Extractor {
+    pub async fn audio_url(&self, url: &str) -> Result<String, String> {
+        if let Some(stream_url) = self.cached(url) {
+            return Ok(stream_url);
+        }
+        let output = run_extractor(&["-f", "bestaudio/best", "--get-url", url]).await?;
+        let stream_url = output
+            .lines()
+            .next()
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .ok_or_else(|| format!("No audio found for {}", url))?;
+        let expires_at = Instant::now() + url_lifetime(&stream_url).saturating_sub(EXPIRY_MARGIN);
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (_, expires_at)| *expires_at > Instant::now());
+        cache.insert(url.to_owned(), (stream_url.clone(), expires_at));
+        Ok(stream_url)
+    }
+
+    fn cached(&self, url: &str) -> Option<String> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(url)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(stream_url, _)| stream_url.clone())
+    }
+}
+
+/// Youtube stream URLs have an `expire=<unix timestamp>` parameter.
+fn url_lifetime(stream_url: &str) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    stream_url
+        .split(['?', '&', '/'])
+        .find_map(|part| part.strip_prefix("expire="))
+        .and_then(|expire| expire.parse::<u64>().ok())
+        .map(|expire| Duration::from_secs(expire.saturating_sub(now)))
+        .unwrap_or(DEFAULT_URL_LIFETIME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix_now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn url_lifetime_reads_the_expire_parameter() {
+        let url = format!(
+            "https://rr1.googlevideo.com/videoplayback?id=1&expire={}&ip=1",
+            unix_now() + 3600
+        );
+        let lifetime = url_lifetime(&url);
+        assert!(lifetime <= Duration::from_secs(3600));
+        assert!(lifetime >= Duration::from_secs(3590));
+        // some links have the parameters in the path
+        let url = format!(
+            "https://manifest.googlevideo.com/api/manifest/expire={}/id/1",
+            unix_now() + 600
+        );
+        assert!(url_lifetime(&url) > Duration::from_secs(590));
+    }
+
+    #[test]
+    fn url_lifetime_of_expired_or_unknown_urls() {
+        let url = format!("https://example.com/audio?expire={}", unix_now() - 60);
+        assert_eq!(url_lifetime(&url), Duration::ZERO);
+        assert_eq!(
+            url_lifetime("https://example.com/audio?expire=soon"),
+            DEFAULT_URL_LIFETIME
+        );
+        assert_eq!(
+            url_lifetime("https://example.com/audio.mp3"),
+            DEFAULT_URL_LIFETIME
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::{
+    library,
+    youtube::{SongEntry, SongSource},
+};
+use regex::Regex;
+use reqwest::Url;
+use std::path::Path;
+
+/// Playlist formats used to share playlists with other people and players.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+    Json,
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "m3u8" | "m3u" => Ok(Self::M3u8),
+            "xspf" => Ok(Self::Xspf),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown playlist format: {} (use .m3u8, .xspf or .json)",
+                path.display()
+            )),
+        }
+    }
+}
+
+pub fn export(songs: &[SongEntry], format: PlaylistFormat) -> Result<String, String> {
+    match format {
+        PlaylistFormat::M3u8 => Ok(export_m3u8(songs)),
+        PlaylistFormat::Xspf => Ok(export_xspf(songs)),
+        PlaylistFormat::Json => serde_json::to_string_pretty(songs).map_err(|e| e.to_string()),
+    }
+}
+
+pub fn import(content: &str, format: PlaylistFormat) -> Result<Vec<SongEntry>, String> {
+    match format {
+        PlaylistFormat::M3u8 => Ok(import_m3u8(content)),
+        PlaylistFormat::Xspf => Ok(import_xspf(content)),
+        PlaylistFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+    }
+}
+
+/// Find the video id in the usual forms of Youtube links.
+pub fn video_id_from_url(url: &str) -> Option<String> {
+    let re = Regex::new(
+        r"(?:youtube\.com/(?:watch\?(?:.*&)?v=|embed/|shorts/)|youtu\.be/)([A-Za-z0-9_-]{11})",
+    )
+    .unwrap();
+    re.captures(url)
+        .and_then(|captures| captures.get(1))
+        .map(|id| id.as_str().to_owned())
+}
+
+/// The ID and source of the song at this location: a Youtube link, a local
+/// file (absolute path or `file://` URL), or any other web link.
+fn song_from_location(location: &str) -> Option<(String, SongSource)> {
+    if let Some(id) = video_id_from_url(location) {
+        return Some((id, SongSource::Youtube));
+    }
+    let path = match Url::parse(location) {
+        Ok(url) if url.scheme() == "file" => url.to_file_path().ok()?,
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+            return Some((location.to_owned(), SongSource::Url));
+        }
+        _ => Path::new(location).to_path_buf(),
+    };
+    if !path.is_absolute() {
+        return None;
+    }
+    Some((path.to_str()?.to_owned(), SongSource::Local))
+}
+
+fn default_title(id: &str, source: SongSource) -> String {
+    match source {
+        SongSource::Local => library::file_title(Path::new(id)),
+        SongSource::Youtube | SongSource::Url | SongSource::Stream => id.to_owned(),
+    }
+}
+
+fn export_m3u8(songs: &[SongEntry]) -> String {
+    let mut content = String::from("#EXTM3U\n");
+    for song in songs {
+        let duration = match (song.source, song.duration) {
+            (SongSource::Stream, _) => -1,
+            (_, Some(duration)) => duration as i64,
+            // -1 is the usual unknown length, but it would turn a link into a
+            // radio when the playlist is imported again
+            (_, None) => 0,
+        };
+        // a line break in the title would end the #EXTINF line
+        let title = song.title.replace(['\r', '\n'], " ");
+        content.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            duration,
+            title,
+            song.location()
+        ));
+    }
+    content
+}
+
+fn import_m3u8(content: &str) -> Vec<SongEntry> {
+    let mut songs = vec![];
+    let mut info: Option<(Option<i64>, String)> = None;
+    for line in content.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            let (duration, title) = extinf.split_once(',').unwrap_or((extinf, ""));
+            let duration = duration
+                .split_whitespace()
+                .next()
+                .and_then(|duration| duration.parse::<i64>().ok());
+            info = Some((duration, title.trim().to_owned()));
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let (duration, title) = info.take().unwrap_or_default();
+            if let Some((id, mut source)) = song_from_location(line) {
+                // a length of -1 is how M3U files list internet radios
+                if source == SongSource::Url && duration == Some(-1) {
+                    source = SongSource::Stream;
+                }
+                songs.push(SongEntry {
+                    title: if title.is_empty() {
+                        default_title(&id, source)
+                    } else {
+                        title
+                    },
+                    id,
+                    source,
+                    duration: duration
+                        .and_then(|duration| u64::try_from(duration).ok())
+                        .filter(|&duration| duration > 0),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    songs
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn export_xspf(songs: &[SongEntry]) -> String {
+    let mut content = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+    for song in songs {
+        // XSPF locations are URIs
+        let location = match song.source {
+            SongSource::Local => Url::from_file_path(&song.id)
+                .map(String::from)
+                .unwrap_or(song.id.clone()),
+            SongSource::Youtube | SongSource::Url | SongSource::Stream => song.location(),
+        };
+        content.push_str("    <track>\n");
+        content.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape_xml(&location)
+        ));
+        content.push_str(&format!(
+            "      <title>{}</title>\n",
+            escape_xml(&song.title)
+        ));
+        if let Some(channel) = &song.channel {
+            content.push_str(&format!(
+                "      <creator>{}</creator>\n",
+                escape_xml(channel)
+            ));
+        }
+        if let Some(duration) = song.duration {
+            // XSPF durations are in milliseconds
+            content.push_str(&format!("      <duration>{}</duration>\n", duration * 1000));
+        }
+        content.push_str("    </track>\n");
+    }
+    content.push_str("  </trackList>\n</playlist>\n");
+    content
+}
+
+fn import_xspf(content: &str) -> Vec<SongEntry> {
+    // the elements can have attributes, like `<track id="1">`
+    let element_re =
+        |name: &str| Regex::new(&format!(r"(?s)<{0}\b[^>]*>(.*?)</{0}>", name)).unwrap();
+    let track_re = element_re("track");
+    let [location_re, title_re, creator_re, duration_re] =
+        ["location", "title", "creator", "duration"].map(element_re);
+    let field = |track: &str, re: &Regex| {
+        re.captures(track)
+            .and_then(|captures| captures.get(1))
+            .map(|value| unescape_xml(value.as_str().trim()))
+    };
+    track_re
+        .captures_iter(content)
+        .filter_map(|captures| {
+            let track = captures.get(1)?.as_str();
+            let (id, source) = song_from_location(&field(track, &location_re)?)?;
+            Some(SongEntry {
+                title: field(track, &title_re).unwrap_or_else(|| default_title(&id, source)),
+                source,
+                channel: field(track, &creator_re),
+                duration: field(track, &duration_re)
+                    .and_then(|duration| duration.parse::<u64>().ok())
+                    .map(|duration| duration / 1000),
+                id,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Fields = (String, String, SongSource, Option<String>, Option<u64>);
+
+    fn song(id: &str, source: SongSource, title: &str, duration: Option<u64>) -> SongEntry {
+        SongEntry {
+            title: title.to_owned(),
+            id: id.to_owned(),
+            source,
+            channel: Some("Channel".to_owned()),
+            duration,
+            ..Default::default()
+        }
+    }
+
+    fn songs() -> Vec<SongEntry> {
+        vec![
+            song(
+                "dQw4w9WgXcQ",
+                SongSource::Youtube,
+                "Rock & <Roll>",
+                Some(213),
+            ),
+            song(
+                "/music/Ünïcode album/01 first song.flac",
+                SongSource::Local,
+                "First song",
+                Some(245),
+            ),
+            song(
+                "https://example.com/song.mp3",
+                SongSource::Url,
+                "A link",
+                None,
+            ),
+            song(
+                "https://radio.example.com/stream",
+                SongSource::Stream,
+                "A radio",
+                None,
+            ),
+        ]
+    }
+
+    fn fields(songs: &[SongEntry], with_channel: bool) -> Vec<Fields> {
+        songs
+            .iter()
+            .map(|song| {
+                (
+                    song.title.clone(),
+                    song.id.clone(),
+                    song.source,
+                    song.channel.clone().filter(|_| with_channel),
+                    song.duration,
+                )
+            })
+            .collect()
+    }
+
+    fn round_trip(format: PlaylistFormat) -> Vec<SongEntry> {
+        import(&export(&songs(), format).unwrap(), format).unwrap()
+    }
+
+    #[test]
+    fn m3u8_round_trip() {
+        let content = export(&songs(), PlaylistFormat::M3u8).unwrap();
+        assert!(content.contains("#EXTINF:0,A link\nhttps://example.com/song.mp3\n"));
+        assert!(content.contains("#EXTINF:-1,A radio\nhttps://radio.example.com/stream\n"));
+        // the M3U8 format has no channel
+        assert_eq!(
+            fields(&round_trip(PlaylistFormat::M3u8), false),
+            fields(&songs(), false)
+        );
+    }
+
+    #[test]
+    fn xspf_round_trip() {
+        let content = export(&songs(), PlaylistFormat::Xspf).unwrap();
+        assert!(content.contains(
+            "<location>file:///music/%C3%9Cn%C3%AFcode%20album/01%20first%20song.flac</location>"
+        ));
+        assert!(content.contains("<title>Rock &amp; &lt;Roll&gt;</title>"));
+        // XSPF can't tell a radio from a link
+        let mut expected = songs();
+        expected[3].source = SongSource::Url;
+        assert_eq!(
+            fields(&round_trip(PlaylistFormat::Xspf), true),
+            fields(&expected, true)
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        assert_eq!(
+            fields(&round_trip(PlaylistFormat::Json), true),
+            fields(&songs(), true)
+        );
+    }
+
+    #[test]
+    fn import_xspf_with_attributes() {
+        let content = r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <trackList>
+    <track id="1">
+      <location>https://www.youtube.com/watch?v=dQw4w9WgXcQ</location>
+      <title xml:lang="en">Never Gonna Give You Up</title>
+      <duration>213000</duration>
+    </track>
+    <track><location>/music/song.ogg</location></track>
+  </trackList>
+</playlist>"#;
+        let songs = import(content, PlaylistFormat::Xspf).unwrap();
+        assert_eq!(
+            fields(&songs, true),
+            [
+                (
+                    "Never Gonna Give You Up".to_owned(),
+                    "dQw4w9WgXcQ".to_owned(),
+                    SongSource::Youtube,
+                    None,
+                    Some(213)
+                ),
+                (
+                    "song".to_owned(),
+                    "/music/song.ogg".to_owned(),
+                    SongSource::Local,
+                    None,
+                    None
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{
+    storage::expand_home,
+    youtube::{SearchQuery, SongEntry, SongSource},
+};
+use std::{
+    env,
+    fs::File,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+use walkdir::WalkDir;
+
+const AUDIO_EXTENSIONS: [&str; 7] = ["mp3", "flac", "ogg", "opus", "m4a", "wav", "aac"];
+
+/// A song of the local library. Its `SongEntry` has the `Local` source, the ID
+/// is the absolute path of the file and the channel is the artist.
+#[derive(Debug, Clone)]
+pub struct LocalSong {
+    pub song: SongEntry,
+    pub album: Option<String>,
+    /// When the file was modified, as it was read.
+    modified: Option<SystemTime>,
+}
+
+impl LocalSong {
+    fn matches(&self, query: &SearchQuery) -> bool {
+        let text = format!(
+            "{} {} {} {}",
+            self.song.title,
+            self.song.channel.as_deref().unwrap_or_default(),
+            self.album.as_deref().unwrap_or_default(),
+            self.song.id
+        )
+        .to_lowercase();
+        let keywords_match = query
+            .keywords
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| text.contains(word));
+        let minutes = self.song.duration.map(|secs| secs as f64 / 60.0);
+        let duration_matches = match (query.duration.as_deref(), minutes) {
+            (Some("short"), Some(minutes)) => minutes < 4.0,
+            (Some("medium"), Some(minutes)) => (4.0..=20.0).contains(&minutes),
+            (Some("long"), Some(minutes)) => minutes > 20.0,
+            _ => true,
+        };
+        let artist_matches = query.channel.as_ref().is_none_or(|channel| {
+            self.song
+                .channel
+                .as_ref()
+                .is_some_and(|artist| artist.to_lowercase().contains(&channel.to_lowercase()))
+        });
+        // files are never live streams
+        keywords_match && duration_matches && artist_matches && query.live != Some(true)
+    }
+}
+
+/// The songs found in the library directories, sorted by path.
+#[derive(Debug, Default)]
+pub struct Library {
+    songs: Vec<LocalSong>,
+    /// The modification time of the directories before the scan.
+    dir_times: Vec<(PathBuf, SystemTime)>,
+}
+
+impl Library {
+    pub fn search(&self, query: &SearchQuery) -> Vec<SongEntry> {
+        self.songs
+            .iter()
+            .filter(|local| local.matches(query))
+            .map(|local| local.song.clone())
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&LocalSong> {
+        // a rescan looks up every file, the songs are sorted to keep it fast
+        let index = self
+            .songs
+            .binary_search_by(|local| local.song.id.as_str().cmp(id))
+            .ok()?;
+        Some(&self.songs[index])
+    }
+
+    /// Songs of the same album or artist.
+    pub fn related(&self, id: &str) -> Vec<SongEntry> {
+        let Some(song) = self.get(id) else {
+            return vec![];
+        };
+        self.songs
+            .iter()
+            .filter(|other| other.song.id != id)
+            .filter(|other| {
+                (song.album.is_some() && other.album == song.album)
+                    || (song.song.channel.is_some() && other.song.channel == song.song.channel)
+            })
+            .map(|other| other.song.clone())
+            .collect()
+    }
+
+    /// Whether files were added, removed or renamed in the directories since
+    /// the scan, which changes the modification time of their directory.
+    pub fn is_outdated(&self, dirs: &[PathBuf]) -> bool {
+        dir_times(dirs) != self.dir_times
+    }
+
+    /// The song of the file, if the file didn't change since it was read.
+    fn unchanged_song(&self, path: &Path) -> Option<&LocalSong> {
+        let modified = path.metadata().ok()?.modified().ok()?;
+        self.get(path.canonicalize().ok()?.to_str()?)
+            .filter(|local| local.modified == Some(modified))
+    }
+}
+
+/// The directories set in `XAUDIO_LIBRARY`, separated like `PATH`.
+pub fn library_dirs() -> Vec<PathBuf> {
+    env::var("XAUDIO_LIBRARY")
+        .map(|dirs| {
+            dirs.split(':')
+                .filter(|dir| !dir.trim().is_empty())
+                .map(|dir| expand_home(dir.trim()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// For the providers, which only get song IDs: Youtube IDs never contain a
+/// `/`, so a local song is anything that looks like an absolute path.
+pub fn is_local_id(id: &str) -> bool {
+    Path::new(id).is_absolute()
+}
+
+/// The path to give to MPV for a local song, MPV plays the file itself.
+pub fn local_file(id: &str) -> Result<String, String> {
+    if Path::new(id).is_file() {
+        Ok(id.to_owned())
+    } else {
+        Err(format!("File not found: {}", id))
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Find the audio files in the directories and read their tags. The files
+/// that didn't change since the `previous` scan are not read again. This can
+/// read every file, so it should not run on the async runtime.
+pub fn scan(dirs: &[PathBuf], previous: Option<&Library>) -> Library {
+    // taken first, so the files added during the scan are found by the next one
+    let dir_times = dir_times(dirs);
+    let mut songs = dirs
+        .iter()
+        .flat_map(|dir| WalkDir::new(dir).follow_links(true))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_audio_file(entry.path()))
+        .filter_map(|entry| {
+            match previous.and_then(|library| library.unchanged_song(entry.path())) {
+                Some(local) => Some(local.clone()),
+                None => read_song(entry.path()),
+            }
+        })
+        .collect::<Vec<LocalSong>>();
+    songs.sort_by(|a, b| a.song.id.cmp(&b.song.id));
+    songs.dedup_by(|a, b| a.song.id == b.song.id);
+    Library { songs, dir_times }
+}
+
+/// The modification time of every directory, in the order they're found.
+fn dir_times(dirs: &[PathBuf]) -> Vec<(PathBuf, SystemTime)> {
+    dirs.iter()
+        .flat_map(|dir| WalkDir::new(dir).follow_links(true).sort_by_file_name())
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir())
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((entry.into_path(), modified))
+        })
+        .collect()
+}
+
+/// The file name without its extension, for files without a title tag.
+pub fn file_title(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Read the tags and the duration of an audio file. A file that can't be
+/// parsed is still a song, MPV may be able to play it.
+pub fn read_song(path: &Path) -> Option<LocalSong> {
+    let path = path.canonicalize().ok()?;
+    let mut local = LocalSong {
+        song: SongEntry {
+            title: file_title(&path),
+            id: path.to_str()?.to_owned(),
+            source: SongSource::Local,
+            ..Default::default()
+        },
+        album: None,
+        modified: path
+            .metadata()
+            .ok()
+            .and_then(|metadata| metadata.modified().ok()),
+    };
+    let file = File::open(&path).ok()?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let Ok(mut probed) = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) else {
+        return Some(local);
+    };
+    // ID3 tags are read before the container, Vorbis comments and MP4 tags
+    // are part of it
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        apply_tags(&mut local, revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        apply_tags(&mut local, revision);
+    }
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        if let (Some(frames), Some(rate)) = (params.n_frames, params.sample_rate) {
+            local.song.duration = Some(frames / rate as u64);
+        }
+    }
+    Some(local)
+}
+
+fn apply_tags(local: &mut LocalSong, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        // RIFF tags keep the padding of the chunk
+        let value = tag
+            .value
+            .to_string()
+            .trim_matches(|ch: char| ch == '\0' || ch.is_whitespace())
+            .to_owned();
+        if value.is_empty() {
+            continue;
+        }
+        match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => local.song.title = value,
+            Some(StandardTagKey::Artist) => local.song.channel = Some(value),
+            Some(StandardTagKey::AlbumArtist) if local.song.channel.is_none() => {
+                local.song.channel = Some(value)
+            }
+            Some(StandardTagKey::Album) => local.album = Some(value),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn rescan_finds_the_new_files() {
+        let dir = env::temp_dir().join(format!("xaudio-library-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("album")).unwrap();
+        // files that can't be parsed are still songs, named after the file
+        fs::write(dir.join("album/first.mp3"), "not audio").unwrap();
+        fs::write(dir.join("cover.jpg"), "not audio").unwrap();
+        // an old directory, so adding a file surely changes its time
+        File::open(dir.join("album"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let dirs = [dir.clone()];
+        let library = scan(&dirs, None);
+        let titles = |library: &Library| {
+            library
+                .songs
+                .iter()
+                .map(|local| local.song.title.clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(titles(&library), ["first"]);
+        assert!(!library.is_outdated(&dirs));
+
+        fs::write(dir.join("album/second.mp3"), "not audio").unwrap();
+        assert!(library.is_outdated(&dirs));
+        let library = scan(&dirs, Some(&library));
+        assert_eq!(titles(&library), ["first", "second"]);
+        for local in &library.songs {
+            assert_eq!(library.get(&local.song.id).unwrap().song.id, local.song.id);
+        }
+        assert!(library.get("/music/missing.mp3").is_none());
+        assert!(!library.is_outdated(&dirs));
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,37 +1,82 @@
+mod cli;
+mod downloads;
+mod editor;
+mod extractor;
+mod formats;
+mod library;
 mod mpv;
+mod provider;
+mod storage;
+mod suggest;
 mod ui;
 mod utils;
 mod youtube;
+mod ytdlp;
 
-use box_drawing::light::HORIZONTAL;
+use box_drawing::{heavy, light::HORIZONTAL};
 use dotenv::dotenv;
-use mpv::MpvClient;
+use downloads::DownloadCache;
+use editor::{EditorAction, LineEditor};
+use mpv::{MpvClient, MpvSupervisor};
 use pancurses::{init_pair, Input, Window, COLOR_BLUE, COLOR_WHITE};
+use provider::Provider;
 use std::{
     collections::HashSet,
+    env,
     fmt::Display,
-    io::Result,
-    thread,
+    io::{ErrorKind, Result},
+    process,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
+use suggest::{is_completion, suggest_from_history, SuggestionSource};
 use tokio::{
     select,
-    sync::mpsc::{Receiver, Sender},
+    sync::mpsc::{Receiver, Sender, UnboundedReceiver},
+    task::JoinHandle,
+    time::sleep,
 };
 use ui::{run, App};
+use unicode_width::UnicodeWidthStr;
 use utils::{
-    create_index_queue, display_time, get_total_pages, paginate, truncate, BACKSPACE_KEY,
-    ENTER_KEY, ESCAPE_KEY, TAB_KEY, TITLE_PADDING,
+    add_to_index_queue, create_index_queue, display_count, display_duration, display_time, fit,
+    get_total_pages, paginate, parse_time, remove_from_index_queue, truncate, unix_timestamp,
+    BACKSPACE_KEY, CTRL_A_KEY, CTRL_B_KEY, CTRL_D_KEY, CTRL_E_KEY, CTRL_F_KEY, CTRL_H_KEY,
+    CTRL_U_KEY, CTRL_W_KEY, ENTER_KEY, ESCAPE_KEY, STATUS_TIMEOUT, TAB_KEY, TITLE_PADDING,
 };
-use youtube::SongEntry;
+use youtube::{playlist_id_from_url, SearchPage, SearchQuery, SongEntry, SongSource};
 
-use crate::utils::{read_playlist, save_playlist};
+use crate::storage::{validate_playlist_name, DEFAULT_PLAYLIST};
+use crate::utils::{MAX_VOLUME, VOLUME_STEP};
 
 #[derive(Debug)]
 enum Command {
     Search(String),
-    Play(String),
-    SavePlaylist(Vec<SongEntry>),
+    SearchMore(String, String),
+    Suggest(String),
+    SaveSearchHistory(Vec<String>),
+    Play(SongEntry),
+    /// Load the song that comes after the playing one, or forget it.
+    Preload(Option<SongEntry>),
+    /// Download the audio of the songs for offline playback.
+    Download(Vec<SongEntry>),
+    Pause,
+    Resume,
+    Seek(i64),
+    SeekTo(Duration),
+    SetVolume(i64),
+    SetMute(bool),
+    SavePlaylist(String, Vec<SongEntry>),
+    LoadPlaylist(String),
+    ListPlaylists,
+    CreatePlaylist(String),
+    RenamePlaylist(String, String),
+    DeletePlaylist(String),
+    ImportPlaylist(String),
+    ExportPlaylist(String, String),
+    ImportYoutubePlaylist(String),
+    FetchRadio(SongEntry, HashSet<String>),
+    Quit,
 }
 
 #[derive(Debug)]
@@ -40,10 +85,20 @@ enum Message {
     GoToSearch,
     GoToSearchBrowse,
     GoToPlaylist,
+    GoToQueue,
+    GoToPlaylistPicker,
+    SwitchPlaylist,
+    OpenPrompt(Prompt),
+    OpenConfirm(Confirmation),
     // Searching and Listing
     SearchSong,
     AddSelectedToPlaylist,
     RemoveSong,
+    // Play queue
+    EnqueueSelected,
+    EnqueueSelectedNext,
+    MoveQueuedUp,
+    MoveQueuedDown,
     NextItem,
     PrevItem,
     NextPage,
@@ -53,73 +108,244 @@ enum Message {
     NextSong,
     PrevSong,
     ToggleShuffle,
+    ToggleRadio,
+    TogglePause,
+    Seek(i64),
+    VolumeUp,
+    VolumeDown,
+    ToggleMute,
+    DownloadSelected,
+    DownloadPlaylist,
     // Input box
     InputText(char),
+    PasteText(String),
     DeleteText,
+    EditInput(EditorAction),
+    HistoryPrev,
+    HistoryNext,
+    AcceptSuggestion,
+    SubmitPrompt,
+    Confirm,
+    CancelInput,
     // Runtime messages
-    DisplaySearchResult(Vec<SongEntry>),
-    SongStarted(Instant),
+    DisplaySearchResult(SearchPage),
+    MoreSearchResults(String, SearchPage),
+    Suggestions(String, Vec<String>),
+    SongStarted,
     SongStopped(String),
     SongDuration(Duration),
+    SongPosition(Duration),
+    SongPaused(bool),
+    PlayerIdle(bool),
+    StreamTitle(Option<String>),
+    /// MPV went on to the preloaded song by itself.
+    PlaylistAdvanced(SongEntry),
+    /// The IDs of the songs that are in the download cache.
+    DownloadedSongs(HashSet<String>),
+    StreamName(String),
+    VolumeChanged(i64),
+    MuteChanged(bool),
+    ShowStatus(String),
+    RadioSongs(Vec<SongEntry>),
+    Playlists(Vec<String>),
+    PlaylistLoaded(String, Vec<SongEntry>),
+    PlaylistRenamed(String, String),
+    PlaylistImported(String, Vec<SongEntry>),
+    YoutubePlaylistSongs(Vec<SongEntry>),
     // Other
     None,
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    SeekTo,
+    NewPlaylist,
+    RenamePlaylist,
+    ImportPlaylist,
+    ExportPlaylist,
+    ImportYoutubePlaylist,
+    AddStream,
+}
+
+impl Prompt {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::SeekTo => "Go to (hh:mm:ss)",
+            Self::NewPlaylist => "New playlist name",
+            Self::RenamePlaylist => "Rename playlist to",
+            Self::ImportPlaylist => "Import from (.m3u8/.xspf/.json)",
+            Self::ExportPlaylist => "Export to (.m3u8/.xspf/.json)",
+            Self::ImportYoutubePlaylist => "Youtube playlist URL or ID",
+            Self::AddStream => "Radio stream URL",
+        }
+    }
+
+    fn return_mode(&self) -> AppMode {
+        match self {
+            Self::SeekTo | Self::ImportYoutubePlaylist | Self::AddStream => AppMode::Playing,
+            _ => AppMode::PlaylistPicker,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Confirmation {
+    Quit,
+    DeletePlaylist,
+}
+
+impl Confirmation {
+    fn return_mode(&self) -> AppMode {
+        match self {
+            Self::Quit => AppMode::Playing,
+            Self::DeletePlaylist => AppMode::PlaylistPicker,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum AppMode {
     Playing,
     SearchInput,
     SearchBrowse,
+    Queue,
+    PlaylistPicker,
+    Prompt(Prompt),
+    Confirm(Confirmation),
+}
+
+impl AppMode {
+    /// The screen that stays visible behind a prompt or a confirmation.
+    fn screen(&self) -> AppMode {
+        match self {
+            Self::Prompt(prompt) => prompt.return_mode(),
+            Self::Confirm(confirmation) => confirmation.return_mode(),
+            mode => *mode,
+        }
+    }
 }
 
 impl Display for AppMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Playing => write!(f, "Now Playing"),
+        match self.screen() {
             Self::SearchInput | Self::SearchBrowse => write!(f, "Song Search"),
+            Self::Queue => write!(f, "Up Next"),
+            Self::PlaylistPicker => write!(f, "Playlists"),
+            _ => write!(f, "Now Playing"),
         }
     }
 }
 
+#[derive(Clone)]
+struct QueueEntry {
+    song: SongEntry,
+    from_radio: bool,
+}
+
+/// How long play counts and durations can wait before the playlist is saved.
+const PLAYLIST_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
 struct MusicApp {
     mode: AppMode,
+    playlist_name: String,
+    playlists: Vec<String>,
     current_playlist: Vec<SongEntry>,
     search_results: Vec<SongEntry>,
+    // past queries (oldest first), the one being recalled with up/down and
+    // what was typed before recalling it
+    search_history: Vec<String>,
+    search_history_position: Option<usize>,
+    search_draft: String,
+    suggestion: Option<String>,
+    // whether the runtime has a suggestion source to ask
+    online_suggestions: bool,
+    // the query of the last search, and the token to load more of its results
+    search_query: String,
+    search_page_token: Option<String>,
+    loading_more: bool,
     current_page: usize,
     page_display_size: usize,
     selected_index: usize,
-    keyword: String,
+    keyword: LineEditor,
+    prompt_input: LineEditor,
     loading: bool,
     subscriber: Sender<Command>,
     song_duration: Duration,
+    song_position: Duration,
     playing: bool,
-    playing_index: usize,
-    last_started: Instant,
+    paused: bool,
+    now_playing: Option<SongEntry>,
+    now_playing_radio: bool,
+    // the ICY title of the song the radio stream is playing
+    stream_title: Option<String>,
+    // the song MPV was asked to load after the playing one
+    preloaded_id: Option<String>,
+    downloaded: HashSet<String>,
+    history: Vec<SongEntry>,
     play_queue: Vec<usize>,
     queue_index: usize,
     is_shuffle: bool,
+    up_next: Vec<QueueEntry>,
+    is_radio: bool,
+    radio_loading: bool,
+    volume: i64,
+    muted: bool,
+    status: Option<(String, Instant)>,
+    // since when the playlist has changes that are not saved yet
+    playlist_changed_at: Option<Instant>,
 }
 
 impl MusicApp {
-    pub fn new(playlist: Vec<SongEntry>, tx: Sender<Command>) -> Self {
+    pub fn new(
+        playlist_name: String,
+        playlist: Vec<SongEntry>,
+        volume: i64,
+        search_history: Vec<String>,
+        online_suggestions: bool,
+        tx: Sender<Command>,
+    ) -> Self {
         let playlist_len = playlist.len();
         Self {
             mode: AppMode::Playing,
+            playlist_name,
+            playlists: vec![],
             current_playlist: playlist,
             search_results: vec![],
+            search_history,
+            search_history_position: None,
+            search_draft: String::new(),
+            suggestion: None,
+            online_suggestions,
+            search_query: String::new(),
+            search_page_token: None,
+            loading_more: false,
             current_page: 0,
             page_display_size: 0,
             selected_index: 0,
-            keyword: String::new(),
+            keyword: LineEditor::default(),
+            prompt_input: LineEditor::default(),
             loading: false,
             subscriber: tx,
             playing: false,
-            playing_index: 0,
-            last_started: Instant::now(),
+            paused: false,
+            now_playing: None,
+            now_playing_radio: false,
+            stream_title: None,
+            preloaded_id: None,
+            downloaded: HashSet::new(),
+            history: vec![],
             song_duration: Duration::default(),
+            song_position: Duration::default(),
             play_queue: create_index_queue(playlist_len, false),
             queue_index: 0,
             is_shuffle: true,
+            up_next: vec![],
+            is_radio: false,
+            radio_loading: false,
+            volume,
+            muted: false,
+            status: None,
+            playlist_changed_at: None,
         }
     }
 
@@ -130,86 +356,576 @@ impl MusicApp {
         win.clear();
     }
 
-    fn input_pop_last(&mut self, win: &Window) {
-        let (cy, cx) = win.get_cur_yx();
-        win.mvprintw(cy, cx - 1, "   ");
-        self.keyword.pop();
+    fn input_buffer(&mut self) -> &mut LineEditor {
+        if let AppMode::Prompt(_) = self.mode {
+            &mut self.prompt_input
+        } else {
+            &mut self.keyword
+        }
     }
 
-    fn input_clear(&mut self, win: &Window) {
-        let (cy, cx) = win.get_cur_yx();
-        let len = self.keyword.len() as i32;
-        win.mv(cy, cx - len);
-        win.clrtoeol();
-        self.keyword.clear();
+    /// Fetch the next page of the search results from the API, they are
+    /// appended to `search_results` when they arrive.
+    fn load_more_results(&mut self) {
+        if self.loading_more {
+            return;
+        }
+        if let Some(token) = self.search_page_token.clone() {
+            _ = self
+                .subscriber
+                .try_send(Command::SearchMore(self.search_query.clone(), token));
+            self.loading_more = true;
+        }
+    }
+
+    fn add_to_search_history(&mut self) {
+        let query = self.keyword.text().trim().to_owned();
+        self.search_history.retain(|entry| *entry != query);
+        self.search_history.push(query);
+        _ = self
+            .subscriber
+            .try_send(Command::SaveSearchHistory(self.search_history.clone()));
+    }
+
+    /// Suggest a completion of the search input, from the search history if
+    /// possible, or else from the suggestion source of the runtime.
+    fn update_suggestion(&mut self) {
+        self.suggestion = None;
+        let keyword = self.keyword.text();
+        if self.mode != AppMode::SearchInput
+            || keyword.trim().is_empty()
+            || !self.keyword.cursor_at_end()
+        {
+            return;
+        }
+        self.suggestion = suggest_from_history(&self.search_history, &keyword);
+        if self.suggestion.is_none() && self.online_suggestions {
+            _ = self.subscriber.try_send(Command::Suggest(keyword));
+        }
+    }
+
+    fn save_playlist(&mut self) {
+        self.playlist_changed_at = None;
+        _ = self.subscriber.try_send(Command::SavePlaylist(
+            self.playlist_name.to_owned(),
+            self.current_playlist.to_owned(),
+        ));
+    }
+
+    /// Append songs to the current playlist, skipping the ones that are already
+    /// in it, and return how many were added.
+    fn add_to_playlist(&mut self, songs: Vec<SongEntry>) -> usize {
+        let mut added = 0;
+        for song in songs {
+            if self
+                .current_playlist
+                .iter()
+                .any(|entry| entry.id == song.id)
+            {
+                continue;
+            }
+            self.current_playlist.push(SongEntry {
+                added_at: Some(unix_timestamp()),
+                ..song
+            });
+            add_to_index_queue(
+                &mut self.play_queue,
+                self.current_playlist.len() - 1,
+                self.queue_index,
+                self.is_shuffle,
+            );
+            added += 1;
+        }
+        if added > 0 {
+            self.save_playlist();
+        }
+        added
+    }
+
+    /// Update the playlist entry of the song that's playing (if it's in the
+    /// playlist). The change is saved with the next save of the playlist, at
+    /// the latest after `PLAYLIST_SAVE_INTERVAL`.
+    fn update_playing_entry(&mut self, update: impl FnOnce(&mut SongEntry)) {
+        let Some(song) = &self.now_playing else {
+            return;
+        };
+        if let Some(entry) = self
+            .current_playlist
+            .iter_mut()
+            .find(|entry| entry.id == song.id)
+        {
+            let before = (entry.title.clone(), entry.duration, entry.play_count);
+            update(entry);
+            if before != (entry.title.clone(), entry.duration, entry.play_count) {
+                self.playlist_changed_at.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    fn selected_playlist(&self) -> Option<String> {
+        self.playlists.get(self.selected_position()).cloned()
+    }
+
+    fn open_prompt(&mut self, prompt: Prompt) {
+        let text = match prompt {
+            Prompt::SeekTo => {
+                if !self.playing {
+                    return;
+                }
+                String::new()
+            }
+            Prompt::NewPlaylist
+            | Prompt::ImportPlaylist
+            | Prompt::ImportYoutubePlaylist
+            | Prompt::AddStream => String::new(),
+            Prompt::RenamePlaylist => match self.selected_playlist() {
+                Some(name) => name,
+                None => return,
+            },
+            Prompt::ExportPlaylist => match self.selected_playlist() {
+                Some(name) => format!("{}.m3u8", name),
+                None => return,
+            },
+        };
+        self.prompt_input.set_text(&text);
+        self.mode = AppMode::Prompt(prompt);
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        self.mode = prompt.return_mode();
+        let input = self.prompt_input.text();
+        match prompt {
+            Prompt::SeekTo => {
+                if let Some(position) = parse_time(&input) {
+                    self.seek_to(position);
+                }
+            }
+            Prompt::NewPlaylist => match validate_playlist_name(&input) {
+                Ok(name) => {
+                    _ = self.subscriber.try_send(Command::CreatePlaylist(name));
+                }
+                Err(e) => self.show_status(e),
+            },
+            Prompt::RenamePlaylist => {
+                let new_name = validate_playlist_name(&input);
+                match (self.selected_playlist(), new_name) {
+                    (Some(name), Ok(new_name)) if name != new_name => {
+                        _ = self
+                            .subscriber
+                            .try_send(Command::RenamePlaylist(name, new_name));
+                    }
+                    (_, Err(e)) => self.show_status(e),
+                    _ => {}
+                }
+            }
+            Prompt::ImportPlaylist => {
+                let path = input.trim().to_owned();
+                if !path.is_empty() {
+                    _ = self.subscriber.try_send(Command::ImportPlaylist(path));
+                }
+            }
+            Prompt::ImportYoutubePlaylist => match playlist_id_from_url(&input) {
+                Some(playlist_id) => {
+                    _ = self
+                        .subscriber
+                        .try_send(Command::ImportYoutubePlaylist(playlist_id));
+                    self.loading = true;
+                }
+                None => self.show_status("Not a Youtube playlist URL or ID".to_owned()),
+            },
+            Prompt::AddStream => {
+                let url = input.trim().to_owned();
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    self.show_status("Not a radio stream URL");
+                    return;
+                }
+                let stream = SongEntry {
+                    title: url.clone(),
+                    id: url,
+                    source: SongSource::Stream,
+                    ..Default::default()
+                };
+                if self.add_to_playlist(vec![stream]) == 0 {
+                    self.show_status("This radio is already in the playlist");
+                }
+            }
+            Prompt::ExportPlaylist => {
+                let path = input.trim().to_owned();
+                if let (Some(name), false) = (self.selected_playlist(), path.is_empty()) {
+                    _ = self
+                        .subscriber
+                        .try_send(Command::ExportPlaylist(name, path));
+                }
+            }
+        }
+    }
+
+    fn confirm(&mut self, confirmation: Confirmation) -> bool {
+        self.mode = confirmation.return_mode();
+        match confirmation {
+            Confirmation::Quit => {
+                self.save_playlist();
+                _ = self.subscriber.try_send(Command::Quit);
+                return false;
+            }
+            Confirmation::DeletePlaylist => {
+                if let Some(name) = self.selected_playlist() {
+                    _ = self
+                        .subscriber
+                        .try_send(Command::DeletePlaylist(name.clone()));
+                    if name == self.playlist_name {
+                        // saving it would bring it back
+                        self.playlist_changed_at = None;
+                        _ = self
+                            .subscriber
+                            .try_send(Command::LoadPlaylist(DEFAULT_PLAYLIST.to_owned()));
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn play_song(&mut self, song: SongEntry, from_radio: bool) {
+        _ = self.subscriber.try_send(Command::Play(song.clone()));
+        // loading a song removes the preloaded one from MPV
+        self.preloaded_id = None;
+        self.set_now_playing(song, from_radio);
+    }
+
+    fn set_now_playing(&mut self, song: SongEntry, from_radio: bool) {
+        self.history.push(song.clone());
+        self.now_playing = Some(song);
+        self.now_playing_radio = from_radio;
+        self.stream_title = None;
+    }
+
+    /// The song `play_next_song()` will play, when it's already known.
+    fn next_song(&self) -> Option<SongEntry> {
+        if let Some(entry) = self.up_next.first() {
+            return Some(entry.song.clone());
+        }
+        let &index = self.play_queue.get(self.queue_index + 1)?;
+        self.current_playlist.get(index).cloned()
+    }
+
+    /// Have MPV load the next song while the current one plays, so there is no
+    /// gap between them. Radios never end, so nothing is loaded after them.
+    fn preload_next_song(&mut self) {
+        let next = if self.playing && !self.playing_stream() {
+            self.next_song()
+        } else {
+            None
+        };
+        let next_id = next.as_ref().map(|song| song.id.clone());
+        if next_id != self.preloaded_id {
+            _ = self.subscriber.try_send(Command::Preload(next));
+            self.preloaded_id = next_id;
+        }
+    }
+
+    fn playing_stream(&self) -> bool {
+        self.now_playing
+            .as_ref()
+            .is_some_and(|song| song.source == SongSource::Stream)
+    }
+
+    fn play_playlist_song(&mut self, index: usize) {
+        if let Some(song) = self.current_playlist.get(index).cloned() {
+            self.select_playlist_song(index);
+            self.play_song(song, false);
+        }
+    }
+
+    fn select_playlist_song(&mut self, index: usize) {
+        if self.mode == AppMode::Playing && self.page_display_size > 0 {
+            self.current_page = index / self.page_display_size;
+            self.selected_index = index % self.page_display_size;
+        }
+    }
+
+    fn selected_position(&self) -> usize {
+        self.selected_index + self.current_page * self.page_display_size
     }
 
     fn play_selected_song(&mut self) {
-        let selected_index = self.selected_index + self.current_page * self.page_display_size;
-        let song = &self.current_playlist[selected_index];
-        _ = self.subscriber.try_send(Command::Play(song.id.to_owned()));
-        self.playing_index = selected_index;
+        self.play_playlist_song(self.selected_position());
     }
 
     fn play_next_song(&mut self) {
-        if self.queue_index < self.play_queue.len() - 1 {
-            self.queue_index += 1;
-        } else {
+        if let Some(entry) = self.advance_queue() {
+            self.play_song(entry.song, entry.from_radio);
+        }
+    }
+
+    /// Move on to the next song of the queue, without playing it.
+    fn advance_queue(&mut self) -> Option<QueueEntry> {
+        if !self.up_next.is_empty() {
+            return Some(self.up_next.remove(0));
+        }
+        let at_end = self.queue_index + 1 >= self.play_queue.len();
+        if at_end && self.is_radio {
+            self.request_radio_songs();
+            return None;
+        }
+        if at_end {
             // rebuild the play queue if needed
             self.play_queue = create_index_queue(self.current_playlist.len(), self.is_shuffle);
             self.queue_index = 0;
+        } else {
+            self.queue_index += 1;
         }
-        self.selected_index = self.play_queue[self.queue_index];
-        self.play_selected_song();
+        let &index = self.play_queue.get(self.queue_index)?;
+        self.select_playlist_song(index);
+        self.current_playlist
+            .get(index)
+            .cloned()
+            .map(|song| QueueEntry {
+                song,
+                from_radio: false,
+            })
+    }
+
+    /// Only Youtube songs need to be downloaded to play offline.
+    fn download(&mut self, songs: Vec<SongEntry>) {
+        let songs = songs
+            .into_iter()
+            .filter(|song| song.source == SongSource::Youtube)
+            .filter(|song| !self.downloaded.contains(&song.id))
+            .collect::<Vec<SongEntry>>();
+        if songs.is_empty() {
+            self.show_status("Nothing to download");
+            return;
+        }
+        self.show_status(format!("Downloading {} songs", songs.len()));
+        _ = self.subscriber.try_send(Command::Download(songs));
+    }
+
+    fn selected_song(&self) -> Option<SongEntry> {
+        let list = match self.mode {
+            AppMode::SearchBrowse => &self.search_results,
+            _ => &self.current_playlist,
+        };
+        list.get(self.selected_position()).cloned()
+    }
+
+    fn enqueue(&mut self, song: SongEntry, next: bool) {
+        let entry = QueueEntry {
+            song,
+            from_radio: false,
+        };
+        if next {
+            self.up_next.insert(0, entry);
+        } else {
+            // songs added by the user always play before the radio ones
+            let position = self
+                .up_next
+                .iter()
+                .position(|entry| entry.from_radio)
+                .unwrap_or(self.up_next.len());
+            self.up_next.insert(position, entry);
+        }
+    }
+
+    fn move_queued(&mut self, up: bool) {
+        let position = self.selected_position();
+        let target = if up {
+            position.checked_sub(1)
+        } else {
+            Some(position + 1).filter(|&target| target < self.up_next.len())
+        };
+        if let Some(target) = target.filter(|_| position < self.up_next.len()) {
+            self.up_next.swap(position, target);
+            self.current_page = target / self.page_display_size;
+            self.selected_index = target % self.page_display_size;
+        }
+    }
+
+    fn request_radio_songs(&mut self) {
+        if self.radio_loading {
+            return;
+        }
+        let seed = self
+            .now_playing
+            .clone()
+            .or_else(|| self.current_playlist.last().cloned());
+        if let Some(seed) = seed {
+            let exclude_ids = self.known_song_ids();
+            _ = self
+                .subscriber
+                .try_send(Command::FetchRadio(seed, exclude_ids));
+            self.radio_loading = true;
+        }
+    }
+
+    fn known_song_ids(&self) -> HashSet<String> {
+        self.history
+            .iter()
+            .chain(self.up_next.iter().map(|entry| &entry.song))
+            .map(|song| song.id.to_owned())
+            .collect()
+    }
+
+    fn toggle_pause(&mut self) {
+        if !self.playing {
+            return;
+        }
+        self.paused = !self.paused;
+        let command = if self.paused {
+            Command::Pause
+        } else {
+            Command::Resume
+        };
+        _ = self.subscriber.try_send(command);
+    }
+
+    fn seek_by(&mut self, offset: i64) {
+        if !self.playing {
+            return;
+        }
+        let position = if offset < 0 {
+            self.song_position
+                .saturating_sub(Duration::from_secs(offset.unsigned_abs()))
+        } else {
+            self.song_position + Duration::from_secs(offset as u64)
+        };
+        self.song_position = position.min(self.song_duration);
+        _ = self.subscriber.try_send(Command::Seek(offset));
+    }
+
+    fn seek_to(&mut self, position: Duration) {
+        if !self.playing {
+            return;
+        }
+        let position = position.min(self.song_duration);
+        self.song_position = position;
+        _ = self.subscriber.try_send(Command::SeekTo(position));
+    }
+
+    fn change_volume(&mut self, delta: i64) {
+        self.volume = (self.volume + delta).clamp(0, MAX_VOLUME);
+        _ = self.subscriber.try_send(Command::SetVolume(self.volume));
     }
 
     fn play_prev_song(&mut self) {
         if self.queue_index > 0 {
             self.queue_index -= 1;
         }
-        self.selected_index = self.play_queue[self.queue_index];
-        self.play_selected_song();
+        if let Some(&index) = self.play_queue.get(self.queue_index) {
+            self.play_playlist_song(index);
+        }
     }
 
     fn draw_base_ui(&self, win: &Window) {
         let (screen_height, screen_width) = win.get_max_yx();
-        let horizontal_line = std::iter::repeat(HORIZONTAL)
-            .take(screen_width as usize)
-            .collect::<String>();
+        let horizontal_line = HORIZONTAL.repeat(screen_width as usize);
         win.mv(0, 0);
         win.clrtoeol();
-        if self.playing {
-            let played_duration = display_time(Instant::now().duration_since(self.last_started));
-            let total_duration = display_time(self.song_duration);
-            let current_song = &self.current_playlist[self.playing_index];
+        if let (true, Some(current_song)) = (self.playing, &self.now_playing) {
+            let played_duration = display_time(self.song_position);
+            // a radio has no end, and may say which song it's playing
+            let (title, time) = match (current_song.source, &self.stream_title) {
+                (SongSource::Stream, Some(stream_title)) => (
+                    truncate(&format!("{}: {}", current_song.title, stream_title), 90),
+                    played_duration,
+                ),
+                (SongSource::Stream, None) => (truncate(&current_song.title, 60), played_duration),
+                _ => (
+                    truncate(&current_song.title, 60),
+                    format!("{} / {}", played_duration, display_time(self.song_duration)),
+                ),
+            };
+            let play_icon = if self.paused { "⏸" } else { "▶" };
             let shuffle_icon = if self.is_shuffle { "~" } else { "" };
+            let radio_marker = if self.now_playing_radio {
+                "[radio] "
+            } else {
+                ""
+            };
             win.mvprintw(
                 0,
                 0,
                 format!(
-                    "▶{} {} - {} / {}",
-                    shuffle_icon,
-                    truncate(&current_song.title, 60),
-                    played_duration,
-                    total_duration
+                    "{}{} {}{} - {}",
+                    play_icon, shuffle_icon, radio_marker, title, time
                 ),
             );
+        } else if self.mode.screen() == AppMode::Playing {
+            win.mvprintw(0, 0, format!("{} - {}", self.mode, self.playlist_name));
         } else {
             win.mvprintw(0, 0, format!("{}", self.mode));
         }
-        win.mvprintw(1, 0, &horizontal_line);
+        let volume = if self.muted {
+            "Vol: muted".to_owned()
+        } else {
+            format!("Vol: {}%", self.volume)
+        };
+        win.mvprintw(0, screen_width - volume.len() as i32 - 1, &volume);
+        let filters = match self.mode {
+            AppMode::SearchInput => SearchQuery::parse(&self.keyword.text()).filters(),
+            AppMode::SearchBrowse => SearchQuery::parse(&self.search_query).filters(),
+            _ => String::new(),
+        };
+        if !filters.is_empty() {
+            let filters = format!("[{}]", filters);
+            win.mvprintw(
+                0,
+                screen_width - (volume.len() + filters.chars().count()) as i32 - 3,
+                filters,
+            );
+        }
+        if self.playing {
+            self.draw_progress_bar(win);
+        } else {
+            win.mvprintw(1, 0, &horizontal_line);
+        }
         win.mvprintw(screen_height - 2, 0, &horizontal_line);
     }
 
+    fn draw_progress_bar(&self, win: &Window) {
+        let (_, screen_width) = win.get_max_yx();
+        let width = screen_width as usize;
+        let played = if self.song_duration.is_zero() {
+            0
+        } else {
+            let ratio = self.song_position.as_secs_f64() / self.song_duration.as_secs_f64();
+            ((ratio.min(1.0) * width as f64) as usize).min(width)
+        };
+        win.mv(1, 0);
+        win.attron(pancurses::COLOR_PAIR(1));
+        win.printw(heavy::HORIZONTAL.repeat(played));
+        win.attroff(pancurses::COLOR_PAIR(1));
+        win.printw(HORIZONTAL.repeat(width - played));
+    }
+
     fn draw_base_instruction(&self, win: &Window) {
         let (screen_height, _) = win.get_max_yx();
         win.mv(screen_height - 1, 1);
         win.clrtoeol();
-        win.printw(format!("[/] Search  [x] Remove  [Enter] Play  [n/p] Next/Prev  [s] Shuffle {}  [Tab] Back to search",
-            if self.is_shuffle { "ON" } else { "OFF" }
+        win.printw(format!("[/] Search  [x] Remove  [Enter] Play  [Space] Pause  [h/l] Seek  [g] Go to  [n/p] Next/Prev  [s] Shuffle {}  [r] Radio {}  [Tab] Back to search",
+            if self.is_shuffle { "ON" } else { "OFF" },
+            if self.is_radio { "ON" } else { "OFF" }
         ));
     }
 
+    fn show_status(&mut self, status: impl Into<String>) {
+        self.status = Some((status.into(), Instant::now()));
+    }
+
+    fn active_status(&self) -> Option<&str> {
+        match &self.status {
+            Some((status, shown_at)) if shown_at.elapsed() < STATUS_TIMEOUT => Some(status),
+            _ => None,
+        }
+    }
+
+    fn draw_status(&self, status: &str, win: &Window) {
+        let (screen_height, _) = win.get_max_yx();
+        win.mv(screen_height - 1, 1);
+        win.clrtoeol();
+        win.printw(status);
+    }
+
     fn draw_loading(&self, win: &Window) {
         let (screen_height, _) = win.get_max_yx();
         win.mv(screen_height - 1, 1);
@@ -217,11 +933,73 @@ impl MusicApp {
         win.printw("Loading...");
     }
 
+    fn draw_input_line(&self, label: &str, editor: &LineEditor, win: &Window) {
+        let (screen_height, screen_width) = win.get_max_yx();
+        win.mv(screen_height - 1, 1);
+        win.clrtoeol();
+        let label = format!("{}: ", label);
+        let width = (screen_width as usize).saturating_sub(label.len() + 2);
+        let (before, cursor, after) = editor.view(width);
+        win.printw(label);
+        win.printw(before);
+        win.attron(pancurses::A_REVERSE);
+        win.printw(cursor);
+        win.attroff(pancurses::A_REVERSE);
+        win.printw(after);
+    }
+
     fn draw_search_box(&self, win: &Window) {
+        self.draw_input_line("Search", &self.keyword, win);
+        if let (Some(suggestion), true) = (&self.suggestion, self.keyword.cursor_at_end()) {
+            // the rest of the suggestion, dimmed, after the cursor
+            let completion = suggestion
+                .chars()
+                .skip(self.keyword.text().chars().count())
+                .collect::<String>();
+            win.attron(pancurses::A_DIM);
+            win.printw(format!("{}  [Tab] Complete", completion));
+            win.attroff(pancurses::A_DIM);
+        }
+    }
+
+    fn draw_prompt_box(&self, prompt: Prompt, win: &Window) {
+        self.draw_input_line(prompt.label(), &self.prompt_input, win);
+    }
+
+    fn draw_confirm(&self, confirmation: Confirmation, win: &Window) {
+        let question = match confirmation {
+            Confirmation::Quit => "Quit xaudio? [y/N]".to_owned(),
+            Confirmation::DeletePlaylist => format!(
+                "Delete playlist {}? [y/N]",
+                self.selected_playlist().unwrap_or_default()
+            ),
+        };
+        self.draw_status(&question, win);
+    }
+
+    fn draw_playlist_picker_instruction(&self, win: &Window) {
         let (screen_height, _) = win.get_max_yx();
         win.mv(screen_height - 1, 1);
         win.clrtoeol();
-        win.mvprintw(screen_height - 1, 1, format!("Search: {}█", self.keyword));
+        win.printw("[j/k] Up/Down  [Enter] Switch  [n] New  [r] Rename  [d] Delete  [i] Import  [e] Export  [Esc] Back to playlist");
+    }
+
+    fn draw_playlist_picker(&self, win: &Window) {
+        let entries = self
+            .playlists
+            .iter()
+            .map(|name| SongEntry {
+                title: name.to_owned(),
+                id: name.to_owned(),
+                ..Default::default()
+            })
+            .collect::<Vec<SongEntry>>();
+        let current = SongEntry {
+            title: self.playlist_name.to_owned(),
+            id: self.playlist_name.to_owned(),
+            ..Default::default()
+        };
+        self.draw_list(&entries, &[current], win);
     }
 
     fn draw_search_instruction(&self, win: &Window) {
@@ -231,6 +1009,28 @@ impl MusicApp {
         win.printw("[j/k] Up/Down    [<] Previous page    [>] Next page    [/] Search");
     }
 
+    fn draw_queue_instruction(&self, win: &Window) {
+        let (screen_height, _) = win.get_max_yx();
+        win.mv(screen_height - 1, 1);
+        win.clrtoeol();
+        win.printw("[j/k] Up/Down  [J/K] Move down/up  [x] Remove  [Enter] Play now  [Esc] Back to playlist");
+    }
+
+    fn draw_queue(&self, win: &Window) {
+        let entries = self
+            .up_next
+            .iter()
+            .map(|entry| {
+                let mut song = entry.song.clone();
+                if entry.from_radio {
+                    song.title = format!("[radio] {}", song.title);
+                }
+                song
+            })
+            .collect::<Vec<SongEntry>>();
+        self.draw_list(&entries, &[], win);
+    }
+
     fn draw_list(&self, list: &[SongEntry], exclude_list: &[SongEntry], win: &Window) {
         let excluded_ids = exclude_list
             .iter()
@@ -238,7 +1038,7 @@ impl MusicApp {
             .collect::<HashSet<String>>();
         let (_, screen_width) = win.get_max_yx();
         let total_pages = get_total_pages(list.len(), self.page_display_size);
-        let page = paginate(&list, self.current_page, self.page_display_size);
+        let page = paginate(list, self.current_page, self.page_display_size);
 
         // clear previous list
         for i in 0..=self.page_display_size as i32 {
@@ -258,10 +1058,20 @@ impl MusicApp {
                     attr_flag |= pancurses::COLOR_PAIR(1);
                 }
                 win.attron(attr_flag);
+                let number = format!("{}. ", i + 1 + self.current_page * self.page_display_size);
+                let downloaded = if self.downloaded.contains(&item.id) {
+                    "↓ "
+                } else {
+                    "  "
+                };
                 win.printw(format!(
-                    "{}. {}\n",
-                    i + 1 + self.current_page * self.page_display_size,
-                    truncate(&item.title, screen_width as usize - TITLE_PADDING)
+                    "{}{}{}\n",
+                    number,
+                    downloaded,
+                    song_row(
+                        item,
+                        (screen_width as usize).saturating_sub(number.len() + 3)
+                    )
                 ));
                 win.attroff(attr_flag);
             }
@@ -272,6 +1082,80 @@ impl MusicApp {
     }
 }
 
+const CHANNEL_WIDTH: usize = 20;
+const DURATION_WIDTH: usize = 8;
+const VIEWS_WIDTH: usize = 13;
+// the title always keeps at least this much room, extra columns are dropped
+// (views first, then channel, then duration) when the terminal is too narrow
+const MIN_TITLE_WIDTH: usize = 30;
+// the channel and views are only shown when the title keeps more room than that
+const MIN_TITLE_WIDTH_WITH_CHANNEL: usize = MIN_TITLE_WIDTH + 10;
+const MIN_TITLE_WIDTH_WITH_VIEWS: usize = MIN_TITLE_WIDTH + 20;
+// the space before each column
+const COLUMN_GAP: usize = 1;
+
+/// Title followed by the channel, duration and view count columns, as much of
+/// them as fits in `width`.
+fn song_row(song: &SongEntry, width: usize) -> String {
+    let show_duration = width >= MIN_TITLE_WIDTH + DURATION_WIDTH + COLUMN_GAP;
+    let show_channel = show_duration
+        && width >= MIN_TITLE_WIDTH_WITH_CHANNEL + CHANNEL_WIDTH + DURATION_WIDTH + 2 * COLUMN_GAP;
+    let show_views = show_channel
+        && width
+            >= MIN_TITLE_WIDTH_WITH_VIEWS
+                + CHANNEL_WIDTH
+                + DURATION_WIDTH
+                + VIEWS_WIDTH
+                + 3 * COLUMN_GAP;
+    if !show_duration {
+        return truncate(&song.title, width.saturating_sub(TITLE_PADDING));
+    }
+    let mut columns = String::new();
+    if show_channel {
+        let channel = song.channel.to_owned().unwrap_or_default();
+        columns += &format!(" {}", fit(&channel, CHANNEL_WIDTH));
+    }
+    let duration = song
+        .duration
+        .map(|secs| display_duration(Duration::from_secs(secs)))
+        .unwrap_or_default();
+    columns += &format!(" {:>width$}", duration, width = DURATION_WIDTH);
+    if show_views {
+        let views = match song.source {
+            SongSource::Youtube => song
+                .view_count
+                .map(|count| display_count(count) + " views")
+                .unwrap_or_default(),
+            SongSource::Url => "link".to_owned(),
+            SongSource::Local => "local file".to_owned(),
+            SongSource::Stream => "radio".to_owned(),
+        };
+        columns += &format!(" {:>width$}", views, width = VIEWS_WIDTH);
+    }
+    let title_width = width - columns.width();
+    fit(&song.title, title_width) + &columns
+}
+
+/// Keys shared by the search box and the prompts.
+fn line_editor_input(input: Input) -> Message {
+    match input {
+        Input::Character(BACKSPACE_KEY) | Input::Character(CTRL_H_KEY) | Input::KeyBackspace => {
+            Message::DeleteText
+        }
+        Input::KeyLeft | Input::Character(CTRL_B_KEY) => Message::EditInput(EditorAction::Left),
+        Input::KeyRight | Input::Character(CTRL_F_KEY) => Message::EditInput(EditorAction::Right),
+        Input::KeyHome | Input::Character(CTRL_A_KEY) => Message::EditInput(EditorAction::Home),
+        Input::KeyEnd | Input::Character(CTRL_E_KEY) => Message::EditInput(EditorAction::End),
+        Input::KeyDC | Input::Character(CTRL_D_KEY) => {
+            Message::EditInput(EditorAction::DeleteForward)
+        }
+        Input::Character(CTRL_W_KEY) => Message::EditInput(EditorAction::DeleteWord),
+        Input::Character(CTRL_U_KEY) => Message::EditInput(EditorAction::KillLine),
+        Input::Character(ch) if !ch.is_control() => Message::InputText(ch),
+        _ => Message::None,
+    }
+}
+
 impl App for MusicApp {
     type Msg = Message;
 
@@ -281,18 +1165,49 @@ impl App for MusicApp {
 
         init_pair(0, COLOR_WHITE, 0);
         init_pair(1, COLOR_BLUE, 0);
+
+        _ = self.subscriber.try_send(Command::SetVolume(self.volume));
     }
 
     fn update(&mut self, win: &Window, msg: Self::Msg) -> bool {
         match msg {
-            Message::DisplaySearchResult(result) => {
-                self.search_results = result;
+            Message::DisplaySearchResult(page) => {
+                self.search_results = page.songs;
+                self.search_page_token = page.next_page_token;
+                self.loading_more = false;
                 self.switch_mode(AppMode::SearchBrowse, win);
                 self.loading = false;
+                if let Some(notice) = page.notice {
+                    self.show_status(notice);
+                }
+            }
+            Message::MoreSearchResults(query, page) => {
+                // results of an older search that came back too late
+                if query != self.search_query {
+                    return true;
+                }
+                self.loading_more = false;
+                self.search_page_token = page.next_page_token;
+                if let Some(notice) = page.notice {
+                    self.show_status(notice);
+                }
+                for song in page.songs {
+                    if !self.search_results.iter().any(|entry| entry.id == song.id) {
+                        self.search_results.push(song);
+                    }
+                }
+                let total_pages =
+                    get_total_pages(self.search_results.len(), self.page_display_size);
+                if self.mode == AppMode::SearchBrowse && self.current_page + 1 < total_pages {
+                    self.current_page += 1;
+                    self.selected_index = 0;
+                }
             }
             Message::GoToSearch => {
                 self.switch_mode(AppMode::SearchInput, win);
-                self.input_clear(win);
+                self.keyword.clear();
+                self.search_history_position = None;
+                self.suggestion = None;
             }
             Message::GoToSearchBrowse => {
                 self.switch_mode(AppMode::SearchBrowse, win);
@@ -300,42 +1215,96 @@ impl App for MusicApp {
             Message::GoToPlaylist => {
                 self.switch_mode(AppMode::Playing, win);
             }
+            Message::GoToPlaylistPicker => {
+                self.switch_mode(AppMode::PlaylistPicker, win);
+                _ = self.subscriber.try_send(Command::ListPlaylists);
+                self.loading = true;
+            }
+            Message::SwitchPlaylist => match self.selected_playlist() {
+                Some(name) if name == self.playlist_name => {
+                    self.switch_mode(AppMode::Playing, win);
+                }
+                Some(name) => {
+                    self.save_playlist();
+                    _ = self.subscriber.try_send(Command::LoadPlaylist(name));
+                }
+                None => {}
+            },
+            Message::OpenPrompt(prompt) => {
+                self.open_prompt(prompt);
+            }
+            Message::OpenConfirm(confirmation) => {
+                if confirmation == Confirmation::DeletePlaylist
+                    && self.selected_playlist().is_none()
+                {
+                    return true;
+                }
+                self.mode = AppMode::Confirm(confirmation);
+            }
             Message::SearchSong => {
-                if self.keyword.trim().len() > 0 {
+                if !self.keyword.text().trim().is_empty() {
+                    self.search_query = self.keyword.text();
+                    self.add_to_search_history();
                     _ = self
                         .subscriber
-                        .try_send(Command::Search(self.keyword.clone()));
+                        .try_send(Command::Search(self.search_query.clone()));
                     self.loading = true;
                 }
             }
             Message::AddSelectedToPlaylist => {
-                let selected_index =
-                    self.selected_index + self.current_page * self.page_display_size;
-                let song = &self.search_results[selected_index];
-                self.current_playlist.push(song.to_owned());
-                _ = self
-                    .subscriber
-                    .try_send(Command::SavePlaylist(self.current_playlist.to_owned()));
-                self.play_queue = create_index_queue(self.current_playlist.len(), self.is_shuffle);
+                if let Some(song) = self.search_results.get(self.selected_position()) {
+                    self.add_to_playlist(vec![song.to_owned()]);
+                }
             }
             Message::RemoveSong => {
-                self.current_playlist.remove(self.selected_index);
-                _ = self
-                    .subscriber
-                    .try_send(Command::SavePlaylist(self.current_playlist.to_owned()));
-                self.play_queue = create_index_queue(self.current_playlist.len(), self.is_shuffle);
+                let position = self.selected_position();
+                if self.mode == AppMode::Queue {
+                    if position < self.up_next.len() {
+                        self.up_next.remove(position);
+                    }
+                } else if position < self.current_playlist.len() {
+                    self.current_playlist.remove(position);
+                    self.save_playlist();
+                    self.queue_index =
+                        remove_from_index_queue(&mut self.play_queue, position, self.queue_index);
+                }
+            }
+            Message::EnqueueSelected | Message::EnqueueSelectedNext => {
+                if let Some(song) = self.selected_song() {
+                    let next = matches!(msg, Message::EnqueueSelectedNext);
+                    self.show_status(format!(
+                        "Queued {}: {}",
+                        if next { "next" } else { "last" },
+                        song.title
+                    ));
+                    self.enqueue(song, next);
+                }
+            }
+            Message::MoveQueuedUp => {
+                self.move_queued(true);
+            }
+            Message::MoveQueuedDown => {
+                self.move_queued(false);
+            }
+            Message::GoToQueue => {
+                self.switch_mode(AppMode::Queue, win);
             }
             Message::NextPage => {
-                let list_len = if self.mode == AppMode::Playing {
-                    self.current_playlist.len()
-                } else {
-                    self.search_results.len()
+                let list_len = match self.mode {
+                    AppMode::Playing => self.current_playlist.len(),
+                    AppMode::Queue => self.up_next.len(),
+                    AppMode::PlaylistPicker => self.playlists.len(),
+                    _ => self.search_results.len(),
                 };
                 let total_pages = get_total_pages(list_len, self.page_display_size);
-                if self.current_page < total_pages - 1 {
+                if self.current_page + 1 < total_pages {
                     self.current_page += 1;
+                    self.selected_index = 0;
+                } else if self.mode == AppMode::SearchBrowse {
+                    self.load_more_results();
+                } else {
+                    self.selected_index = 0;
                 }
-                self.selected_index = 0;
             }
             Message::PrevPage => {
                 if self.current_page > 0 {
@@ -354,26 +1323,154 @@ impl App for MusicApp {
                 }
             }
             Message::InputText(ch) => {
-                self.keyword.push(ch);
+                self.input_buffer().insert(ch);
+                self.update_suggestion();
+            }
+            Message::PasteText(text) => {
+                self.input_buffer().insert_str(&text);
+                self.update_suggestion();
             }
             Message::DeleteText => {
-                self.input_pop_last(win);
+                self.input_buffer().backspace();
+                self.update_suggestion();
+            }
+            Message::EditInput(action) => {
+                let accept_suggestion = self.mode == AppMode::SearchInput
+                    && action == EditorAction::Right
+                    && self.keyword.cursor_at_end();
+                match self.suggestion.take() {
+                    Some(suggestion) if accept_suggestion => self.keyword.set_text(&suggestion),
+                    _ => self.input_buffer().apply(action),
+                }
+                self.update_suggestion();
+            }
+            Message::HistoryPrev => {
+                let position = match self.search_history_position {
+                    _ if self.search_history.is_empty() => return true,
+                    None => {
+                        self.search_draft = self.keyword.text();
+                        self.search_history.len() - 1
+                    }
+                    Some(position) => position.saturating_sub(1),
+                };
+                self.search_history_position = Some(position);
+                self.keyword.set_text(&self.search_history[position]);
+                self.suggestion = None;
+            }
+            Message::HistoryNext => {
+                match self.search_history_position {
+                    Some(position) if position + 1 < self.search_history.len() => {
+                        self.search_history_position = Some(position + 1);
+                        self.keyword.set_text(&self.search_history[position + 1]);
+                    }
+                    Some(_) => {
+                        self.search_history_position = None;
+                        self.keyword.set_text(&self.search_draft);
+                    }
+                    None => {}
+                }
+                self.suggestion = None;
+            }
+            Message::AcceptSuggestion => {
+                if let Some(suggestion) = self.suggestion.take() {
+                    self.keyword.set_text(&suggestion);
+                }
+            }
+            Message::Suggestions(prefix, suggestions) => {
+                if self.mode == AppMode::SearchInput && prefix == self.keyword.text() {
+                    self.suggestion = suggestions
+                        .into_iter()
+                        .find(|suggestion| is_completion(suggestion, &prefix));
+                }
+            }
+            Message::SubmitPrompt => {
+                if let AppMode::Prompt(prompt) = self.mode {
+                    self.submit_prompt(prompt);
+                }
+            }
+            Message::Confirm => {
+                if let AppMode::Confirm(confirmation) = self.mode {
+                    return self.confirm(confirmation);
+                }
+            }
+            Message::CancelInput => {
+                self.mode = self.mode.screen();
             }
             Message::PlaySelected => {
-                self.play_selected_song();
+                if self.mode == AppMode::Queue {
+                    let position = self.selected_position();
+                    if position < self.up_next.len() {
+                        let entry = self.up_next.remove(position);
+                        self.play_song(entry.song, entry.from_radio);
+                    }
+                } else {
+                    self.play_selected_song();
+                }
             }
-            Message::SongStarted(current_time) => {
+            Message::SongStarted => {
                 self.playing = true;
-                self.last_started = current_time;
+                self.song_position = Duration::default();
+                self.update_playing_entry(|entry| entry.play_count += 1);
             }
             Message::SongStopped(reason) => {
                 self.playing = false;
-                if reason.eq("eof") {
+                // a radio never ends, unless the connection is lost
+                if reason.eq("eof") && self.playing_stream() {
+                    self.show_status("The radio stream stopped");
+                } else if reason.eq("eof") {
                     self.play_next_song();
                 }
             }
+            Message::SongDuration(_) if self.playing_stream() => {
+                self.song_duration = Duration::default();
+            }
             Message::SongDuration(duration) => {
                 self.song_duration = duration;
+                if duration.as_secs() > 0 {
+                    self.update_playing_entry(|entry| entry.duration = Some(duration.as_secs()));
+                }
+            }
+            Message::SongPosition(position) => {
+                self.song_position = position;
+            }
+            Message::SongPaused(paused) => {
+                self.paused = paused;
+            }
+            Message::PlayerIdle(idle) => {
+                if idle {
+                    self.playing = false;
+                }
+            }
+            Message::PlaylistAdvanced(song) => {
+                self.preloaded_id = None;
+                if self.next_song().is_some_and(|next| next.id == song.id) {
+                    if let Some(entry) = self.advance_queue() {
+                        self.set_now_playing(entry.song, entry.from_radio);
+                    }
+                } else {
+                    // the queue changed since the song was preloaded
+                    self.play_next_song();
+                }
+            }
+            Message::StreamTitle(title) => {
+                if self.playing_stream() {
+                    self.stream_title = title.filter(|title| !title.trim().is_empty());
+                }
+            }
+            Message::StreamName(name) => {
+                // streams are added with their URL as title, until they say their name
+                if self.playing_stream() {
+                    self.update_playing_entry(|entry| {
+                        if entry.title == entry.id {
+                            entry.title = name.clone();
+                        }
+                    });
+                    if let Some(song) = self.now_playing.as_mut() {
+                        if song.title == song.id {
+                            song.title = name;
+                        }
+                    }
+                }
             }
             Message::NextSong => {
                 self.play_next_song();
@@ -385,50 +1482,228 @@ impl App for MusicApp {
                 self.is_shuffle = !self.is_shuffle;
                 self.play_queue = create_index_queue(self.current_playlist.len(), self.is_shuffle);
             }
-            Message::None => {}
-        }
-        return true;
-    }
-
-    fn input(&mut self, input: Input) -> Self::Msg {
-        match self.mode {
-            AppMode::Playing => {
-                return match input {
-                    Input::Character(ENTER_KEY) => Message::PlaySelected,
-                    Input::Character('/') => Message::GoToSearch,
-                    Input::Character(TAB_KEY) => Message::GoToSearchBrowse,
-                    Input::Character('j') => Message::NextItem,
-                    Input::Character('k') => Message::PrevItem,
-                    Input::Character('x') => Message::RemoveSong,
-                    Input::Character('>') => Message::NextPage,
-                    Input::Character('<') => Message::PrevPage,
-                    Input::Character('n') => Message::NextSong,
-                    Input::Character('p') => Message::PrevSong,
-                    Input::Character('s') => Message::ToggleShuffle,
-                    _ => Message::None,
+            Message::ToggleRadio => {
+                self.is_radio = !self.is_radio;
+                if !self.is_radio {
+                    self.up_next.retain(|entry| !entry.from_radio);
+                }
+            }
+            Message::RadioSongs(songs) => {
+                self.radio_loading = false;
+                let known_ids = self.known_song_ids();
+                let songs = songs
+                    .into_iter()
+                    .filter(|song| !known_ids.contains(&song.id))
+                    .collect::<Vec<SongEntry>>();
+                // the radio may have been turned off while the songs were fetched
+                if self.is_radio && songs.is_empty() {
+                    self.show_status("No related songs found for the radio");
+                } else if self.is_radio {
+                    self.up_next
+                        .extend(songs.into_iter().map(|song| QueueEntry {
+                            song,
+                            from_radio: true,
+                        }));
+                    if !self.playing {
+                        self.play_next_song();
+                    }
+                }
+            }
+            Message::TogglePause => {
+                self.toggle_pause();
+            }
+            Message::Seek(offset) => {
+                self.seek_by(offset);
+            }
+            Message::VolumeUp => {
+                self.change_volume(VOLUME_STEP);
+            }
+            Message::VolumeDown => {
+                self.change_volume(-VOLUME_STEP);
+            }
+            Message::ToggleMute => {
+                self.muted = !self.muted;
+                _ = self.subscriber.try_send(Command::SetMute(self.muted));
+            }
+            Message::DownloadSelected => {
+                if let Some(song) = self.selected_song() {
+                    self.download(vec![song]);
                 }
             }
-            AppMode::SearchInput => {
-                return match input {
-                    Input::Character(ESCAPE_KEY) => Message::GoToPlaylist,
-                    Input::Character(BACKSPACE_KEY) => Message::DeleteText,
-                    Input::Character(ENTER_KEY) => Message::SearchSong,
-                    Input::Character(ch) => Message::InputText(ch),
-                    _ => Message::None,
+            Message::DownloadPlaylist => {
+                self.download(self.current_playlist.clone());
+            }
+            Message::DownloadedSongs(ids) => {
+                self.downloaded = ids;
+            }
+            Message::VolumeChanged(volume) => {
+                self.volume = volume;
+            }
+            Message::MuteChanged(muted) => {
+                self.muted = muted;
+            }
+            Message::ShowStatus(status) => {
+                self.show_status(status);
+                self.loading = false;
+            }
+            Message::Playlists(names) => {
+                self.playlists = names;
+                if self.selected_position() >= self.playlists.len() {
+                    self.current_page = 0;
+                    self.selected_index = 0;
                 }
+                self.loading = false;
+            }
+            Message::PlaylistLoaded(name, songs) => {
+                self.playlist_name = name;
+                self.current_playlist = songs;
+                self.playlist_changed_at = None;
+                self.play_queue = create_index_queue(self.current_playlist.len(), self.is_shuffle);
+                self.queue_index = 0;
+                self.switch_mode(AppMode::Playing, win);
             }
-            AppMode::SearchBrowse => {
-                return match input {
-                    Input::Character(ESCAPE_KEY) | Input::Character('q') => Message::GoToPlaylist,
-                    Input::Character('/') => Message::GoToSearch,
-                    Input::Character('>') => Message::NextPage,
-                    Input::Character('<') => Message::PrevPage,
-                    Input::Character('j') => Message::NextItem,
-                    Input::Character('k') => Message::PrevItem,
-                    Input::Character(ENTER_KEY) => Message::AddSelectedToPlaylist,
-                    _ => Message::None,
+            Message::PlaylistImported(name, songs) => {
+                if name == self.playlist_name {
+                    for index in self.current_playlist.len()..songs.len() {
+                        add_to_index_queue(
+                            &mut self.play_queue,
+                            index,
+                            self.queue_index,
+                            self.is_shuffle,
+                        );
+                    }
+                    self.current_playlist = songs;
+                }
+            }
+            Message::YoutubePlaylistSongs(songs) => {
+                self.loading = false;
+                let total = songs.len();
+                let added = self.add_to_playlist(songs);
+                self.show_status(format!(
+                    "Imported {} songs into {} ({} already in the playlist)",
+                    added,
+                    self.playlist_name,
+                    total - added
+                ));
+            }
+            Message::PlaylistRenamed(name, new_name) => {
+                if self.playlist_name == name {
+                    self.playlist_name = new_name;
                 }
             }
+            Message::None => {}
+        }
+        // whatever changed the queue, MPV should load what comes next
+        self.preload_next_song();
+        if self
+            .playlist_changed_at
+            .is_some_and(|changed_at| changed_at.elapsed() >= PLAYLIST_SAVE_INTERVAL)
+        {
+            self.save_playlist();
+        }
+        true
+    }
+
+    fn input(&mut self, input: Input) -> Self::Msg {
+        match self.mode {
+            AppMode::Playing => match input {
+                Input::Character(ENTER_KEY) => Message::PlaySelected,
+                Input::Character('/') => Message::GoToSearch,
+                Input::Character(TAB_KEY) => Message::GoToSearchBrowse,
+                Input::Character('j') => Message::NextItem,
+                Input::Character('k') => Message::PrevItem,
+                Input::Character('x') => Message::RemoveSong,
+                Input::Character('>') => Message::NextPage,
+                Input::Character('<') => Message::PrevPage,
+                Input::Character('n') => Message::NextSong,
+                Input::Character('p') => Message::PrevSong,
+                Input::Character('s') => Message::ToggleShuffle,
+                Input::Character('r') => Message::ToggleRadio,
+                Input::Character('a') => Message::EnqueueSelected,
+                Input::Character('A') => Message::EnqueueSelectedNext,
+                Input::Character('u') => Message::GoToQueue,
+                Input::Character(' ') => Message::TogglePause,
+                Input::Character('h') | Input::KeyLeft => Message::Seek(-5),
+                Input::Character('l') | Input::KeyRight => Message::Seek(5),
+                Input::Character('H') => Message::Seek(-30),
+                Input::Character('L') => Message::Seek(30),
+                Input::Character('g') => Message::OpenPrompt(Prompt::SeekTo),
+                Input::Character('P') => Message::GoToPlaylistPicker,
+                Input::Character('I') => Message::OpenPrompt(Prompt::ImportYoutubePlaylist),
+                Input::Character('R') => Message::OpenPrompt(Prompt::AddStream),
+                Input::Character('q') => Message::OpenConfirm(Confirmation::Quit),
+                Input::Character('+') | Input::Character('=') => Message::VolumeUp,
+                Input::Character('-') => Message::VolumeDown,
+                Input::Character('m') => Message::ToggleMute,
+                Input::Character('d') => Message::DownloadSelected,
+                Input::Character('D') => Message::DownloadPlaylist,
+                _ => Message::None,
+            },
+            AppMode::Confirm(_) => match input {
+                Input::Character('y') | Input::Character(ENTER_KEY) => Message::Confirm,
+                _ => Message::CancelInput,
+            },
+            AppMode::Prompt(_) => match input {
+                Input::Character(ESCAPE_KEY) => Message::CancelInput,
+                Input::Character(ENTER_KEY) => Message::SubmitPrompt,
+                input => line_editor_input(input),
+            },
+            AppMode::SearchInput => match input {
+                Input::Character(ESCAPE_KEY) => Message::GoToPlaylist,
+                Input::Character(ENTER_KEY) => Message::SearchSong,
+                Input::Character(TAB_KEY) => Message::AcceptSuggestion,
+                Input::KeyUp => Message::HistoryPrev,
+                Input::KeyDown => Message::HistoryNext,
+                input => line_editor_input(input),
+            },
+            AppMode::SearchBrowse => match input {
+                Input::Character(ESCAPE_KEY) | Input::Character('q') => Message::GoToPlaylist,
+                Input::Character('/') => Message::GoToSearch,
+                Input::Character('>') => Message::NextPage,
+                Input::Character('<') => Message::PrevPage,
+                Input::Character('j') => Message::NextItem,
+                Input::Character('k') => Message::PrevItem,
+                Input::Character(ENTER_KEY) => Message::AddSelectedToPlaylist,
+                Input::Character('a') => Message::EnqueueSelected,
+                Input::Character('A') => Message::EnqueueSelectedNext,
+                _ => Message::None,
+            },
+            AppMode::Queue => match input {
+                Input::Character(ESCAPE_KEY) | Input::Character('q') => Message::GoToPlaylist,
+                Input::Character('/') => Message::GoToSearch,
+                Input::Character('>') => Message::NextPage,
+                Input::Character('<') => Message::PrevPage,
+                Input::Character('j') => Message::NextItem,
+                Input::Character('k') => Message::PrevItem,
+                Input::Character('J') => Message::MoveQueuedDown,
+                Input::Character('K') => Message::MoveQueuedUp,
+                Input::Character('x') => Message::RemoveSong,
+                Input::Character(ENTER_KEY) => Message::PlaySelected,
+                Input::Character(' ') => Message::TogglePause,
+                Input::Character('n') => Message::NextSong,
+                _ => Message::None,
+            },
+            AppMode::PlaylistPicker => match input {
+                Input::Character(ESCAPE_KEY) | Input::Character('q') => Message::GoToPlaylist,
+                Input::Character('>') => Message::NextPage,
+                Input::Character('<') => Message::PrevPage,
+                Input::Character('j') => Message::NextItem,
+                Input::Character('k') => Message::PrevItem,
+                Input::Character(ENTER_KEY) => Message::SwitchPlaylist,
+                Input::Character('n') => Message::OpenPrompt(Prompt::NewPlaylist),
+                Input::Character('r') => Message::OpenPrompt(Prompt::RenamePlaylist),
+                Input::Character('d') => Message::OpenConfirm(Confirmation::DeletePlaylist),
+                Input::Character('i') => Message::OpenPrompt(Prompt::ImportPlaylist),
+                Input::Character('e') => Message::OpenPrompt(Prompt::ExportPlaylist),
+                _ => Message::None,
+            },
+        }
+    }
+
+    fn paste(&mut self, text: String) -> Option<Self::Msg> {
+        match self.mode {
+            AppMode::SearchInput | AppMode::Prompt(_) => Some(Message::PasteText(text)),
+            _ => None,
         }
     }
 
@@ -437,95 +1712,861 @@ impl App for MusicApp {
 
         if self.loading {
             self.draw_loading(win);
+        } else if self.loading_more && self.mode == AppMode::SearchBrowse {
+            self.draw_status("Loading more results...", win);
         } else {
-            match self.mode {
-                AppMode::SearchInput => {
+            match (&self.mode, self.active_status()) {
+                (AppMode::SearchInput, _) => {
                     self.draw_search_box(win);
                 }
-                AppMode::SearchBrowse => {
+                (AppMode::Prompt(prompt), _) => {
+                    self.draw_prompt_box(*prompt, win);
+                }
+                (AppMode::Confirm(confirmation), _) => {
+                    self.draw_confirm(*confirmation, win);
+                }
+                (_, Some(status)) => {
+                    self.draw_status(status, win);
+                }
+                (AppMode::SearchBrowse, _) => {
                     self.draw_search_instruction(win);
                 }
+                (AppMode::Queue, _) => {
+                    self.draw_queue_instruction(win);
+                }
+                (AppMode::PlaylistPicker, _) => {
+                    self.draw_playlist_picker_instruction(win);
+                }
                 _ => self.draw_base_instruction(win),
             }
         }
 
-        if let AppMode::Playing = self.mode {
-            let highlight_playing = if self.playing {
-                vec![self.current_playlist[self.playing_index].clone()]
-            } else {
-                vec![]
+        match self.mode.screen() {
+            AppMode::Queue => self.draw_queue(win),
+            AppMode::PlaylistPicker => self.draw_playlist_picker(win),
+            AppMode::Playing => {
+                let highlight_playing = match (self.playing, &self.now_playing) {
+                    (true, Some(song)) => vec![song.clone()],
+                    _ => vec![],
+                };
+                self.draw_list(&self.current_playlist, &highlight_playing, win);
+            }
+            _ => self.draw_list(&self.search_results, &self.current_playlist, win),
+        }
+    }
+}
+
+/// How long the typing has to pause before asking for suggestions.
+const SUGGESTION_DELAY: Duration = Duration::from_millis(300);
+
+/// How long to wait for the duration of a song before playing it anyway.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn handle_player_command(
+    mpv: &mut MpvClient,
+    provider: &dyn Provider,
+    downloads: &Mutex<DownloadCache>,
+    command: Command,
+    tx: &Sender<Message>,
+) -> std::result::Result<(), String> {
+    match command {
+        Command::Play(song) => {
+            // a downloaded song plays offline, MPV reports its duration
+            let downloaded = downloads.lock().unwrap().contains(&song.id);
+            let song_duration = async {
+                match song.source {
+                    SongSource::Youtube if !downloaded => {
+                        tokio::time::timeout(METADATA_TIMEOUT, provider.metadata(&song.id))
+                            .await
+                            .ok()
+                            .and_then(|metadata| metadata.ok())
+                            .and_then(|metadata| metadata.duration)
+                            .or(song.duration)
+                    }
+                    SongSource::Youtube
+                    | SongSource::Url
+                    | SongSource::Local
+                    | SongSource::Stream => song.duration,
+                }
             };
-            self.draw_list(&self.current_playlist, &highlight_playing, win);
-        } else {
-            self.draw_list(&self.search_results, &self.current_playlist, win);
+            // both can take a while, don't wait for one before starting the other
+            let (song_duration, url) =
+                tokio::join!(song_duration, stream_url(provider, downloads, &song));
+            _ = tx
+                .send(Message::SongDuration(
+                    song_duration.map(Duration::from_secs).unwrap_or_default(),
+                ))
+                .await;
+            mpv.load_song(&url?).await?;
+            mpv.play().await?;
+            mpv.unpause().await
+        }
+        Command::Pause => mpv.pause().await,
+        Command::Resume => mpv.unpause().await,
+        Command::Seek(offset) => mpv.seek_relative(offset).await,
+        Command::SeekTo(position) => mpv.seek_to(position).await,
+        _ => Ok(()),
+    }
+}
+
+/// The URL MPV plays the song from, the downloaded file when there is one.
+async fn stream_url(
+    provider: &dyn Provider,
+    downloads: &Mutex<DownloadCache>,
+    song: &SongEntry,
+) -> std::result::Result<String, String> {
+    match song.source {
+        SongSource::Youtube => {
+            let cached_file = downloads.lock().unwrap().cached_file(&song.id);
+            match cached_file {
+                Some(file) => Ok(file),
+                None => provider.stream_url(&song.id).await,
+            }
+        }
+        SongSource::Local => library::local_file(&song.id),
+        // MPV opens links by itself, with its own youtube-dl hook for pages
+        SongSource::Url | SongSource::Stream => Ok(song.id.clone()),
+    }
+}
+
+/// Download the songs one at a time, in the background.
+async fn download_songs(
+    mut rx: UnboundedReceiver<SongEntry>,
+    downloads: Arc<Mutex<DownloadCache>>,
+    tx: Sender<Message>,
+) {
+    while let Some(song) = rx.recv().await {
+        if downloads.lock().unwrap().contains(&song.id) {
+            continue;
         }
+        let status = match downloads::download(&downloads, &song.id).await {
+            Ok(()) => format!("Downloaded {}", song.title),
+            Err(e) => format!("Cannot download {}: {}", song.title, e),
+        };
+        let ids = downloads.lock().unwrap().ids();
+        _ = tx.send(Message::DownloadedSongs(ids)).await;
+        _ = tx.send(Message::ShowStatus(status)).await;
     }
 }
 
-async fn runtime(mut rx: Receiver<Command>, tx: Sender<Message>) {
-    let mut mpv = MpvClient::new().await;
+/// The song appended after the playing one in MPV's playlist, so MPV goes from
+/// one to the other without a gap.
+#[derive(Default)]
+struct Preload {
+    // the song whose stream URL is being resolved
+    wanted: Option<String>,
+    // the song that is in MPV's playlist, right after the playing one
+    loaded: Option<SongEntry>,
+    // the playing song ended and MPV is moving on to the loaded one
+    advancing: bool,
+    // messages about the new song, held until `playlist-pos` says it's playing
+    held: Vec<Message>,
+}
+
+/// Runs the commands of the app until it quits. The provider, the suggestion
+/// source, the player and the download cache are given by the caller, so tests
+/// can use fake ones.
+async fn runtime(
+    mut rx: Receiver<Command>,
+    tx: Sender<Message>,
+    provider: Box<dyn Provider>,
+    suggestions: Box<dyn SuggestionSource>,
+    mut player: MpvSupervisor,
+    downloads: DownloadCache,
+) {
+    let suggestions: Arc<dyn SuggestionSource> = Arc::from(suggestions);
+    // shared with the tasks that resolve the preloaded songs
+    let provider: Arc<dyn Provider> = Arc::from(provider);
+    let (preload_tx, mut preload_rx) = tokio::sync::mpsc::channel(4);
+    let mut preload = Preload::default();
+    let downloads = Arc::new(Mutex::new(downloads));
+    let downloaded_ids = downloads.lock().unwrap().ids();
+    _ = tx.send(Message::DownloadedSongs(downloaded_ids)).await;
+    let (download_tx, download_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(download_songs(download_rx, downloads.clone(), tx.clone()));
+    if let Err(e) = player.client().await {
+        _ = tx.send(Message::ShowStatus(e)).await;
+    }
+    let mut last_position = None;
+    // only the results of the last search are shown
+    let mut search: Option<JoinHandle<()>> = None;
+    let mut suggestion: Option<JoinHandle<()>> = None;
     loop {
         select! {
             app_command = rx.recv() => {
-                if let Some(msg) = app_command {
-                    match msg {
-                        Command::Search(keyword) => {
-                            if let Ok(results) = youtube::search_song(&keyword).await {
-                                _ = tx.send(Message::DisplaySearchResult(results)).await;
+                let Some(msg) = app_command else {
+                    break;
+                };
+                match msg {
+                    // searches can wait for the network or the library scan,
+                    // they run on their own so the playback isn't held up
+                    Command::Search(keyword) => {
+                        if let Some(search) = search.take() {
+                            search.abort();
+                        }
+                        let provider = provider.clone();
+                        let tx = tx.clone();
+                        search = Some(tokio::spawn(async move {
+                            match provider.search(&keyword, None).await {
+                                Ok(page) => {
+                                    _ = tx.send(Message::DisplaySearchResult(page)).await;
+                                }
+                                Err(e) => {
+                                    _ = tx.send(Message::ShowStatus(e)).await;
+                                }
                             }
+                        }));
+                    }
+                    // only ask for the last prefix once the typing pauses
+                    Command::Suggest(prefix) => {
+                        if let Some(suggestion) = suggestion.take() {
+                            suggestion.abort();
                         }
-                        Command::Play(song_id) => {
-                            let song_duration = youtube::get_song_duration(&song_id).await.unwrap_or_default();
-                            _ = tx.send(Message::SongDuration(song_duration)).await;
-                            mpv.load_song(format!("https://www.youtube.com/watch?v={}", song_id).as_str()).await;
-                            mpv.play().await;
+                        let suggestions = suggestions.clone();
+                        let tx = tx.clone();
+                        suggestion = Some(tokio::spawn(async move {
+                            sleep(SUGGESTION_DELAY).await;
+                            if let Ok(suggestions) = suggestions.suggest(&prefix).await {
+                                _ = tx.send(Message::Suggestions(prefix, suggestions)).await;
+                            }
+                        }));
+                    }
+                    Command::SaveSearchHistory(history) => {
+                        _ = storage::save_search_history(&history);
+                    }
+                    Command::SearchMore(keyword, page_token) => {
+                        let provider = provider.clone();
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            let page = match provider.search(&keyword, Some(&page_token)).await {
+                                Ok(page) => page,
+                                Err(e) => {
+                                    _ = tx.send(Message::ShowStatus(e)).await;
+                                    // keep the token so the user can try again
+                                    SearchPage {
+                                        songs: vec![],
+                                        next_page_token: Some(page_token),
+                                        ..Default::default()
+                                    }
+                                }
+                            };
+                            _ = tx.send(Message::MoreSearchResults(keyword, page)).await;
+                        });
+                    }
+                    Command::SetVolume(volume) => {
+                        _ = player.set_sticky_property("volume", volume.into()).await;
+                        _ = storage::save_volume(volume);
+                    }
+                    Command::SetMute(muted) => {
+                        _ = player.set_sticky_property("mute", muted.into()).await;
+                    }
+                    Command::SavePlaylist(name, playlist) => {
+                        if let Err(e) = storage::save_playlist(&name, &playlist) {
+                            _ = tx.send(Message::ShowStatus(format!("Cannot save playlist {}: {}", name, e))).await;
                         }
-                        Command::SavePlaylist(current_playlist) => {
-                            _ = save_playlist(&current_playlist);
+                    }
+                    Command::ListPlaylists => {
+                        let names = storage::list_playlists().unwrap_or_default();
+                        _ = tx.send(Message::Playlists(names)).await;
+                    }
+                    Command::LoadPlaylist(name) => {
+                        let songs = match storage::read_playlist(&name) {
+                            Ok(songs) => songs,
+                            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+                            Err(e) => {
+                                _ = tx.send(Message::ShowStatus(format!("Cannot open playlist {}: {}", name, e))).await;
+                                continue;
+                            }
+                        };
+                        _ = storage::save_current_playlist_name(&name);
+                        _ = tx.send(Message::PlaylistLoaded(name, songs)).await;
+                    }
+                    Command::CreatePlaylist(name) => {
+                        if let Err(e) = storage::create_playlist(&name) {
+                            _ = tx.send(Message::ShowStatus(e.to_string())).await;
                         }
+                        let names = storage::list_playlists().unwrap_or_default();
+                        _ = tx.send(Message::Playlists(names)).await;
                     }
+                    Command::RenamePlaylist(name, new_name) => {
+                        match storage::rename_playlist(&name, &new_name) {
+                            Ok(()) => {
+                                if storage::read_current_playlist_name().is_ok_and(|current| current == name) {
+                                    _ = storage::save_current_playlist_name(&new_name);
+                                }
+                                _ = tx.send(Message::PlaylistRenamed(name, new_name)).await;
+                            }
+                            Err(e) => {
+                                _ = tx.send(Message::ShowStatus(e.to_string())).await;
+                            }
+                        }
+                        let names = storage::list_playlists().unwrap_or_default();
+                        _ = tx.send(Message::Playlists(names)).await;
+                    }
+                    Command::DeletePlaylist(name) => {
+                        if let Err(e) = storage::delete_playlist(&name) {
+                            _ = tx.send(Message::ShowStatus(e.to_string())).await;
+                        }
+                        let names = storage::list_playlists().unwrap_or_default();
+                        _ = tx.send(Message::Playlists(names)).await;
+                    }
+                    Command::ImportPlaylist(path) => {
+                        match storage::import_playlist(&storage::expand_home(&path), None) {
+                            Ok((name, songs, added)) => {
+                                _ = tx.send(Message::PlaylistImported(name.to_owned(), songs)).await;
+                                _ = tx.send(Message::ShowStatus(format!("Imported {} songs into playlist {}", added, name))).await;
+                            }
+                            Err(e) => {
+                                _ = tx.send(Message::ShowStatus(format!("Cannot import {}: {}", path, e))).await;
+                            }
+                        }
+                        let names = storage::list_playlists().unwrap_or_default();
+                        _ = tx.send(Message::Playlists(names)).await;
+                    }
+                    Command::ExportPlaylist(name, path) => {
+                        let status = match storage::export_playlist(&name, &storage::expand_home(&path)) {
+                            Ok(count) => format!("Exported {} songs to {}", count, path),
+                            Err(e) => format!("Cannot export playlist {}: {}", name, e),
+                        };
+                        _ = tx.send(Message::ShowStatus(status)).await;
+                    }
+                    // a long playlist takes many pages to fetch
+                    Command::ImportYoutubePlaylist(playlist_id) => {
+                        let provider = provider.clone();
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            match provider.playlist_songs(&playlist_id).await {
+                                Ok(songs) => {
+                                    _ = tx.send(Message::YoutubePlaylistSongs(songs)).await;
+                                }
+                                Err(e) => {
+                                    _ = tx.send(Message::ShowStatus(e)).await;
+                                }
+                            }
+                        });
+                    }
+                    // like a search, it can wait for the network or a scan
+                    Command::FetchRadio(seed, exclude_ids) => {
+                        let provider = provider.clone();
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            let mut seen_ids = exclude_ids;
+                            let related = match seed.source {
+                                SongSource::Youtube | SongSource::Local => {
+                                    provider.related(&seed.id).await
+                                }
+                                // nothing to find similar songs with
+                                SongSource::Url | SongSource::Stream => Ok(vec![]),
+                            };
+                            match related {
+                                Ok(songs) => {
+                                    let songs = songs
+                                        .into_iter()
+                                        .filter(|song| seen_ids.insert(song.id.to_owned()))
+                                        .collect();
+                                    _ = tx.send(Message::RadioSongs(songs)).await;
+                                }
+                                Err(e) => {
+                                    _ = tx.send(Message::RadioSongs(vec![])).await;
+                                    _ = tx.send(Message::ShowStatus(e)).await;
+                                }
+                            }
+                        });
+                    }
+                    Command::Preload(Some(song)) => {
+                        preload.wanted = Some(song.id.clone());
+                        let provider = provider.clone();
+                        let downloads = downloads.clone();
+                        let preload_tx = preload_tx.clone();
+                        tokio::spawn(async move {
+                            let url = stream_url(provider.as_ref(), &downloads, &song).await;
+                            _ = preload_tx.send((song, url)).await;
+                        });
+                    }
+                    Command::Preload(None) => {
+                        preload.wanted = None;
+                        // too late when MPV is already moving on to the loaded
+                        // song, `PlaylistAdvanced` tells the app it's playing
+                        if !preload.advancing && preload.loaded.take().is_some() {
+                            if let Ok(mpv) = player.client().await {
+                                _ = mpv.clear_playlist().await;
+                            }
+                        }
+                    }
+                    Command::Download(songs) => {
+                        for song in songs {
+                            _ = download_tx.send(song);
+                        }
+                    }
+                    Command::Quit => {
+                        break;
+                    }
+                    command => {
+                        // `loadfile replace` drops the preloaded song
+                        if let Command::Play(_) = command {
+                            preload = Preload::default();
+                        }
+                        let result = match player.client().await {
+                            Ok(mpv) => handle_player_command(mpv, provider.as_ref(), &downloads, command, &tx).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            _ = tx.send(Message::ShowStatus(e)).await;
+                        }
+                    }
+                }
+            },
+            Some((song, url)) = preload_rx.recv() => {
+                // the queue may have changed while the URL was resolved
+                if preload.advancing || preload.wanted.as_ref() != Some(&song.id) {
+                    continue;
+                }
+                preload.wanted = None;
+                // when it fails, the song gets another try when it's played
+                let (Ok(url), Ok(mpv)) = (url, player.client().await) else {
+                    continue;
+                };
+                // the song that was preloaded before is not next anymore
+                if mpv.clear_playlist().await.is_ok() && mpv.append_song(&url).await.is_ok() {
+                    preload.loaded = Some(song);
                 }
             },
-            mpv_event = mpv.recv() => {
-                if let Ok(event) = mpv_event {
-                    match event {
-                        mpv::MpvEvent::StartFile => {
-                            _ = tx.send(Message::SongStarted(Instant::now())).await;
-                        },
-                        mpv::MpvEvent::EndFile(reason) => {
-                            _ = tx.send(Message::SongStopped(reason)).await;
-                        },
-                        _ => {}
-                        // mpv::MpvEvent::Unknown(_event) => {
-                        //     println!("UNKNOWN: {:?}", _event);
-                        // }
+            mpv_event = player.recv() => {
+                match mpv_event {
+                    // MPV goes on to the preloaded song by itself, after an
+                    // error too
+                    Ok(mpv::MpvEvent::EndFile(reason))
+                        if preload.loaded.is_some() && (reason == "eof" || reason == "error") =>
+                    {
+                        preload.advancing = true;
+                    },
+                    Ok(mpv::MpvEvent::StartFile) if preload.advancing => {
+                        preload.held.push(Message::SongStarted);
+                    },
+                    Ok(mpv::MpvEvent::Duration(duration)) if preload.advancing => {
+                        preload.held.push(Message::SongDuration(duration));
+                    },
+                    Ok(mpv::MpvEvent::PlaylistPos(pos))
+                        if pos > 0 && (preload.advancing || preload.loaded.is_some()) =>
+                    {
+                        preload.advancing = false;
+                        if let Some(song) = preload.loaded.take() {
+                            _ = tx.send(Message::PlaylistAdvanced(song)).await;
+                        }
+                        for msg in preload.held.drain(..) {
+                            _ = tx.send(msg).await;
+                        }
+                        // keep the playing song first, like after a `loadfile replace`
+                        if let Ok(mpv) = player.client().await {
+                            _ = mpv.remove_from_playlist(0).await;
+                        }
+                    },
+                    Ok(mpv::MpvEvent::StartFile) => {
+                        _ = tx.send(Message::SongStarted).await;
+                    },
+                    Ok(mpv::MpvEvent::EndFile(reason)) => {
+                        _ = tx.send(Message::SongStopped(reason)).await;
+                    },
+                    // mpv reports time-pos many times per second, only the
+                    // whole seconds are interesting for the UI
+                    Ok(mpv::MpvEvent::TimePos(position)) if last_position != Some(position.as_secs()) => {
+                        last_position = Some(position.as_secs());
+                        _ = tx.send(Message::SongPosition(position)).await;
+                    },
+                    Ok(mpv::MpvEvent::Duration(duration)) => {
+                        _ = tx.send(Message::SongDuration(duration)).await;
+                    },
+                    Ok(mpv::MpvEvent::Pause(paused)) => {
+                        _ = tx.send(Message::SongPaused(paused)).await;
+                    },
+                    Ok(mpv::MpvEvent::Idle(idle)) => {
+                        _ = tx.send(Message::PlayerIdle(idle)).await;
+                    },
+                    Ok(mpv::MpvEvent::Volume(volume)) => {
+                        _ = tx.send(Message::VolumeChanged(volume)).await;
+                    },
+                    Ok(mpv::MpvEvent::Mute(muted)) => {
+                        _ = tx.send(Message::MuteChanged(muted)).await;
+                    },
+                    Ok(mpv::MpvEvent::Metadata(metadata)) => {
+                        if let Some(name) = metadata.get("icy-name") {
+                            _ = tx.send(Message::StreamName(name.to_owned())).await;
+                        }
+                        _ = tx.send(Message::StreamTitle(metadata.get("icy-title").cloned())).await;
+                    },
+                    Ok(_) => {}
+                    Err(_) => {
+                        // the connection is gone, most likely mpv crashed
+                        preload = Preload::default();
+                        _ = tx.send(Message::SongStopped("error".to_owned())).await;
+                        let status = match player.restart().await {
+                            Ok(()) => "MPV stopped unexpectedly and was restarted".to_owned(),
+                            Err(e) => format!("MPV stopped unexpectedly: {}", e),
+                        };
+                        _ = tx.send(Message::ShowStatus(status)).await;
                     }
                 }
             }
         }
     }
+    // the last use of the played songs is only saved with the downloads
+    downloads.lock().unwrap().save();
+    player.shutdown().await;
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
-    tokio::spawn(async move {
-        MpvClient::start_server().await;
-    });
-    // Want to know why a 500ms delay? It's a long story.
-    // Once upon a time, there was a process called "mpv" spawned
-    // after the dotenv().ok() statement. It carries the responsibility
-    // of being an RPC server that our runtime will be connected to.
-    // This process takes a few milliseconds to start, if we just start
-    // the MusicApp right away, the connection would be failed.
-    // Hence, we wait 500ms.
-    thread::sleep(Duration::from_millis(500));
-    let (cmd_tx, cmd_rx) = tokio::sync::mpsc::channel::<Command>(1);
+    _ = storage::migrate_legacy_files();
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if !args.is_empty() {
+        match cli::run_command(&args) {
+            Ok(output) => println!("{}", output),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let (cmd_tx, cmd_rx) = tokio::sync::mpsc::channel::<Command>(32);
     let (msg_tx, msg_rx) = tokio::sync::mpsc::channel::<Message>(1);
 
-    let playlist = read_playlist().unwrap_or(vec![]);
-    let app = MusicApp::new(playlist, cmd_tx);
-    tokio::spawn(runtime(cmd_rx, msg_tx));
+    let playlist_name =
+        storage::read_current_playlist_name().unwrap_or(DEFAULT_PLAYLIST.to_owned());
+    let playlist = storage::read_playlist(&playlist_name).unwrap_or(vec![]);
+    let volume = storage::read_volume().unwrap_or(MAX_VOLUME);
+    let search_history = storage::read_search_history().unwrap_or_default();
+    let suggestions = suggest::suggestion_source();
+    let app = MusicApp::new(
+        playlist_name,
+        playlist,
+        volume,
+        search_history,
+        suggestions.is_enabled(),
+        cmd_tx,
+    );
+    let runtime = tokio::spawn(runtime(
+        cmd_rx,
+        msg_tx,
+        provider::from_config(),
+        suggestions,
+        MpvSupervisor::new(),
+        DownloadCache::open(),
+    ));
+    // the runtime stops on Command::Quit, or when the app (which owns the
+    // command sender) is dropped, and shuts mpv down before returning
     run(app, false, msg_rx);
+    _ = runtime.await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use provider::FakeProvider;
+    use serde_json::{json, Value};
+    use std::{fs, path::PathBuf};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+        sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+        time::timeout,
+    };
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn song_rows_fill_the_width_with_wide_channel_names() {
+        let song = SongEntry {
+            title: "Never Gonna Give You Up".to_owned(),
+            id: "dQw4w9WgXcQ".to_owned(),
+            channel: Some("リック・アストリー公式チャンネル".to_owned()),
+            duration: Some(213),
+            view_count: Some(1_500_000_000),
+            ..Default::default()
+        };
+        for width in [40, 80, 120] {
+            assert_eq!(song_row(&song, width).width(), width);
+        }
+        let row = song_row(&song, 120);
+        assert!(row.starts_with("Never Gonna Give You Up "));
+        assert!(row.contains(" リック・アストリー"));
+        assert!(row.contains(" 3:33 "));
+    }
+
+    /// An mpv that accepts every command and passes them on to the test, and
+    /// sends the events the test gives it.
+    fn fake_mpv() -> (
+        MpvSupervisor,
+        UnboundedReceiver<Value>,
+        UnboundedSender<Value>,
+    ) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        let (events, mut events_rx) = mpsc::unbounded_channel::<Value>();
+        tokio::spawn(async move {
+            let (read, mut write) = server.into_split();
+            let mut lines = BufReader::new(read).lines();
+            loop {
+                let (reply, command) = select! {
+                    line = lines.next_line() => {
+                        let Ok(Some(line)) = line else {
+                            break;
+                        };
+                        let request = serde_json::from_str::<Value>(&line).unwrap();
+                        let reply = json!({
+                            "request_id": request["request_id"],
+                            "error": "success",
+                            "data": null,
+                        });
+                        (reply, Some(request["command"].clone()))
+                    }
+                    Some(event) = events_rx.recv() => (event, None),
+                };
+                if write
+                    .write_all(format!("{}\n", reply).as_bytes())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                if let Some(command) = command {
+                    _ = commands_tx.send(command);
+                }
+            }
+        });
+        (MpvSupervisor::connected(client), commands, events)
+    }
+
+    /// Suggests the prefix followed by " remix", and keeps the prefixes it
+    /// was asked for.
+    #[derive(Default)]
+    struct StubSuggestions {
+        prefixes: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SuggestionSource for StubSuggestions {
+        async fn suggest(&self, prefix: &str) -> std::result::Result<Vec<String>, String> {
+            self.prefixes.lock().unwrap().push(prefix.to_owned());
+            Ok(vec![format!("{} remix", prefix)])
+        }
+    }
+
+    /// A runtime with a fake provider and a fake mpv, and an empty download
+    /// cache in a temporary directory.
+    struct TestRuntime {
+        commands: Sender<Command>,
+        messages: Receiver<Message>,
+        mpv_commands: UnboundedReceiver<Value>,
+        mpv_events: UnboundedSender<Value>,
+        task: JoinHandle<()>,
+        cache_dir: PathBuf,
+    }
+
+    impl TestRuntime {
+        async fn start(name: &str, songs: Vec<SongEntry>, suggestions: StubSuggestions) -> Self {
+            let (player, mpv_commands, mpv_events) = fake_mpv();
+            let cache_dir = env::temp_dir().join(format!("xaudio-{}-{}", name, process::id()));
+            let (commands, cmd_rx) = mpsc::channel(32);
+            let (msg_tx, messages) = mpsc::channel(1);
+            let task = tokio::spawn(runtime(
+                cmd_rx,
+                msg_tx,
+                Box::new(FakeProvider { songs }),
+                Box::new(suggestions),
+                player,
+                DownloadCache::open_in(cache_dir.clone(), 0),
+            ));
+            let mut runtime = Self {
+                commands,
+                messages,
+                mpv_commands,
+                mpv_events,
+                task,
+                cache_dir,
+            };
+            match runtime.next_message().await {
+                Message::DownloadedSongs(ids) => assert!(ids.is_empty()),
+                msg => panic!("unexpected message {:?}", msg),
+            }
+            runtime
+        }
+
+        async fn send(&self, command: Command) {
+            self.commands.send(command).await.unwrap();
+        }
+
+        async fn next_message(&mut self) -> Message {
+            timeout(TEST_TIMEOUT, self.messages.recv())
+                .await
+                .expect("no message from the runtime")
+                .expect("the runtime stopped")
+        }
+
+        fn mpv_event(&self, event: Value) {
+            self.mpv_events.send(event).unwrap();
+        }
+
+        async fn next_mpv_command(&mut self) -> Value {
+            timeout(TEST_TIMEOUT, self.mpv_commands.recv())
+                .await
+                .expect("no command sent to mpv")
+                .expect("the fake mpv stopped")
+        }
+
+        async fn quit(self) {
+            self.send(Command::Quit).await;
+            timeout(TEST_TIMEOUT, self.task).await.unwrap().unwrap();
+            _ = fs::remove_dir_all(&self.cache_dir);
+        }
+    }
+
+    fn song(id: &str, title: &str, duration: u64) -> SongEntry {
+        SongEntry {
+            id: id.to_owned(),
+            title: title.to_owned(),
+            duration: Some(duration),
+            ..Default::default()
+        }
+    }
+
+    fn ids(songs: &[SongEntry]) -> Vec<&str> {
+        songs.iter().map(|song| song.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn runtime_searches_plays_and_fetches_the_radio() {
+        let songs = vec![
+            song("queen000001", "Queen - Bohemian Rhapsody", 354),
+            song("queen000002", "Queen - Under Pressure", 248),
+            song("bowie000001", "David Bowie - Heroes", 371),
+        ];
+        let mut runtime = TestRuntime::start("runtime", songs.clone(), Default::default()).await;
+
+        runtime.send(Command::Search("queen".to_owned())).await;
+        match runtime.next_message().await {
+            Message::DisplaySearchResult(page) => {
+                assert_eq!(ids(&page.songs), ["queen000001", "queen000002"]);
+                assert_eq!(page.next_page_token, None);
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+
+        runtime.send(Command::Play(songs[0].clone())).await;
+        match runtime.next_message().await {
+            Message::SongDuration(duration) => assert_eq!(duration, Duration::from_secs(354)),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["loadfile", "fake://queen000001", "replace"])
+        );
+
+        let exclude_ids = HashSet::from(["queen000001".to_owned(), "queen000002".to_owned()]);
+        runtime
+            .send(Command::FetchRadio(songs[0].clone(), exclude_ids))
+            .await;
+        match runtime.next_message().await {
+            Message::RadioSongs(songs) => assert_eq!(ids(&songs), ["bowie000001"]),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        runtime.quit().await;
+    }
+
+    #[tokio::test]
+    async fn runtime_moves_on_to_the_preloaded_song() {
+        let songs = vec![
+            song("queen000001", "Queen - Bohemian Rhapsody", 354),
+            song("queen000002", "Queen - Under Pressure", 248),
+        ];
+        let mut runtime = TestRuntime::start("preload", songs.clone(), Default::default()).await;
+        runtime.send(Command::Play(songs[0].clone())).await;
+        assert!(matches!(
+            runtime.next_message().await,
+            Message::SongDuration(_)
+        ));
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["loadfile", "fake://queen000001", "replace"])
+        );
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["playlist-play-index", "0"])
+        );
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["set_property", "pause", false])
+        );
+
+        runtime.send(Command::Preload(Some(songs[1].clone()))).await;
+        assert_eq!(runtime.next_mpv_command().await, json!(["playlist-clear"]));
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["loadfile", "fake://queen000002", "append"])
+        );
+
+        // the events of the next song are held until it's the playing one
+        runtime.mpv_event(json!({"event": "end-file", "reason": "eof"}));
+        runtime.mpv_event(json!({"event": "start-file"}));
+        runtime.mpv_event(json!({"event": "property-change", "name": "duration", "data": 248.0}));
+        runtime.mpv_event(json!({"event": "property-change", "name": "pause", "data": false}));
+        assert!(matches!(
+            runtime.next_message().await,
+            Message::SongPaused(false)
+        ));
+        // forgetting the next song is too late now, MPV is already on it
+        runtime.send(Command::Preload(None)).await;
+        runtime.send(Command::Pause).await;
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["set_property", "pause", true])
+        );
+
+        runtime.mpv_event(json!({"event": "property-change", "name": "playlist-pos", "data": 1}));
+        match runtime.next_message().await {
+            Message::PlaylistAdvanced(song) => assert_eq!(song.id, "queen000002"),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert!(matches!(runtime.next_message().await, Message::SongStarted));
+        match runtime.next_message().await {
+            Message::SongDuration(duration) => assert_eq!(duration, Duration::from_secs(248)),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["playlist-remove", "0"])
+        );
+
+        // nothing is held anymore
+        runtime.mpv_event(json!({"event": "start-file"}));
+        assert!(matches!(runtime.next_message().await, Message::SongStarted));
+        runtime.quit().await;
+    }
+
+    #[tokio::test]
+    async fn runtime_only_asks_suggestions_for_the_last_prefix() {
+        let suggestions = StubSuggestions::default();
+        let prefixes = suggestions.prefixes.clone();
+        let mut runtime = TestRuntime::start("suggestions", vec![], suggestions).await;
+        for prefix in ["q", "qu", "que"] {
+            runtime.send(Command::Suggest(prefix.to_owned())).await;
+        }
+        // the pending suggestion doesn't hold up the other commands
+        runtime.send(Command::Pause).await;
+        assert_eq!(
+            runtime.next_mpv_command().await,
+            json!(["set_property", "pause", true])
+        );
+        match runtime.next_message().await {
+            Message::Suggestions(prefix, suggestions) => {
+                assert_eq!(prefix, "que");
+                assert_eq!(suggestions, ["que remix"]);
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert_eq!(*prefixes.lock().unwrap(), ["que"]);
+        runtime.quit().await;
+    }
+}
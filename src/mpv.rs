@@ -1,102 +1,402 @@
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::{
         unix::{OwnedReadHalf, OwnedWriteHalf},
         UnixStream,
     },
+    process::Child,
+    sync::{mpsc, oneshot},
+    time::{sleep, timeout},
 };
 
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+const SOCKET_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_START_ATTEMPTS: usize = 3;
+
 #[derive(Debug)]
 pub enum MpvEvent {
     StartFile,
     EndFile(String),
+    TimePos(Duration),
+    Duration(Duration),
+    Pause(bool),
+    Idle(bool),
+    Volume(i64),
+    Mute(bool),
+    /// Tags of the playing file, for streams it has the ICY headers and the
+    /// `icy-title` of the current song.
+    Metadata(HashMap<String, String>),
+    /// Index of the playing entry in MPV's playlist.
+    PlaylistPos(i64),
+    #[allow(dead_code)]
     Unknown(String),
 }
 
+type Reply = Result<Value, String>;
+
+// Replies waiting for their request_id to come back from mpv. Becomes `None`
+// once the connection is closed, so no new request can wait forever.
+type PendingReplies = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Reply>>>>>;
+
 pub struct MpvClient {
-    reader: BufReader<OwnedReadHalf>,
     writer: OwnedWriteHalf,
+    events: mpsc::UnboundedReceiver<MpvEvent>,
+    pending: PendingReplies,
+    next_request_id: u64,
 }
 
-impl MpvClient {
-    pub async fn start_server() {
-        _ = tokio::process::Command::new("mpv")
-            .arg("--input-ipc-server=/tmp/mpv-socket")
+/// Owns the mpv process: spawns it on a socket unique to this process, waits
+/// for the socket to accept connections, restarts it when it crashes and
+/// kills it on shutdown.
+pub struct MpvSupervisor {
+    socket_path: PathBuf,
+    process: Option<Child>,
+    client: Option<MpvClient>,
+    // properties set through the supervisor are restored after a restart
+    sticky_properties: Vec<(String, Value)>,
+}
+
+impl MpvSupervisor {
+    pub fn new() -> Self {
+        Self {
+            socket_path: env::temp_dir().join(format!("xaudio-mpv-{}.sock", process::id())),
+            process: None,
+            client: None,
+            sticky_properties: vec![],
+        }
+    }
+
+    /// A supervisor connected to an mpv that is already running, like the fake
+    /// one of the tests.
+    #[cfg(test)]
+    pub fn connected(stream: UnixStream) -> Self {
+        Self {
+            client: Some(MpvClient::new(stream)),
+            ..Self::new()
+        }
+    }
+
+    /// Get the connected client, starting mpv first if it's not running.
+    pub async fn client(&mut self) -> Result<&mut MpvClient, String> {
+        if self.client.is_none() {
+            self.start().await?;
+        }
+        self.client
+            .as_mut()
+            .ok_or_else(|| "MPV is not running".to_owned())
+    }
+
+    /// Wait for the next event of the running mpv. Never resolves while mpv is
+    /// not running, so it's safe to use inside `select!`.
+    pub async fn recv(&mut self) -> std::io::Result<MpvEvent> {
+        match self.client.as_mut() {
+            Some(client) => client.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    pub async fn restart(&mut self) -> Result<(), String> {
+        self.shutdown().await;
+        let mut last_error = String::new();
+        for _ in 0..MAX_START_ATTEMPTS {
+            match self.start().await {
+                Ok(()) => return Ok(()),
+                Err(e) => last_error = e,
+            }
+            self.shutdown().await;
+        }
+        Err(last_error)
+    }
+
+    pub async fn set_sticky_property(
+        &mut self,
+        property: &str,
+        value: Value,
+    ) -> Result<(), String> {
+        self.sticky_properties.retain(|(name, _)| name != property);
+        self.sticky_properties
+            .push((property.to_owned(), value.clone()));
+        self.client().await?.set_property(property, value).await
+    }
+
+    pub async fn shutdown(&mut self) {
+        if let Some(mut client) = self.client.take() {
+            _ = client.stop().await;
+            _ = client.send(vec!["quit"]).await;
+        }
+        if let Some(mut process) = self.process.take() {
+            if timeout(SHUTDOWN_TIMEOUT, process.wait()).await.is_err() {
+                _ = process.kill().await;
+            }
+        }
+        _ = fs::remove_file(&self.socket_path);
+    }
+
+    async fn start(&mut self) -> Result<(), String> {
+        _ = fs::remove_file(&self.socket_path);
+        let mut process = tokio::process::Command::new("mpv")
+            .arg(format!("--input-ipc-server={}", self.socket_path.display()))
             .arg("--no-terminal")
             .arg("--no-video")
             .arg("--idle")
+            // play the song appended after the current one without a gap,
+            // and start loading it before the current one ends
+            .arg("--gapless-audio=yes")
+            .arg("--prefetch-playlist=yes")
+            .kill_on_drop(true)
             .spawn()
-            .expect("Cannot start MPV server")
-            .wait()
-            .await;
+            .map_err(|e| format!("Cannot start MPV: {}", e))?;
+        // if mpv never becomes ready, the process is dropped (and killed) here
+        let stream = wait_for_socket(&self.socket_path, &mut process).await?;
+        self.process = Some(process);
+        let mut client = MpvClient::new(stream);
+        client.observe_playback().await?;
+        for (property, value) in self.sticky_properties.iter() {
+            client.set_property(property, value).await?;
+        }
+        self.client = Some(client);
+        Ok(())
     }
+}
 
-    pub async fn new() -> Self {
-        let stream = UnixStream::connect("/tmp/mpv-socket")
-            .await
-            .expect("Cannot connect to MPV");
+async fn wait_for_socket(socket_path: &Path, process: &mut Child) -> Result<UnixStream, String> {
+    let started = Instant::now();
+    loop {
+        if let Ok(stream) = UnixStream::connect(socket_path).await {
+            return Ok(stream);
+        }
+        if let Ok(Some(status)) = process.try_wait() {
+            return Err(format!("MPV exited before it was ready ({})", status));
+        }
+        if started.elapsed() > SOCKET_TIMEOUT {
+            return Err("Timed out waiting for MPV to start".to_owned());
+        }
+        sleep(SOCKET_POLL_INTERVAL).await;
+    }
+}
+
+impl MpvClient {
+    fn new(stream: UnixStream) -> Self {
         let (read, write) = stream.into_split();
-        let reader = BufReader::new(read);
+        let (event_tx, events) = mpsc::unbounded_channel();
+        let pending: PendingReplies = Arc::new(Mutex::new(Some(HashMap::new())));
+        tokio::spawn(read_messages(
+            BufReader::new(read),
+            pending.clone(),
+            event_tx,
+        ));
         Self {
-            reader,
             writer: write,
+            events,
+            pending,
+            next_request_id: 1,
         }
     }
 
-    pub async fn send(&mut self, args: Vec<&str>) {
-        _ = self
-            .writer
-            .write_all(json!({ "command": args }).to_string().as_bytes())
-            .await;
-        _ = self.writer.write_u8('\n' as u8).await;
+    /// Send a command to mpv and wait for its reply, which is matched to the
+    /// command by `request_id`. Events that arrive in the meantime are queued
+    /// and can still be read with `recv()`.
+    pub async fn command(&mut self, args: Value) -> Reply {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(request_id, reply_tx),
+            None => return Err("Connection to MPV is closed".to_owned()),
+        };
+        let mut line = json!({ "command": args, "request_id": request_id }).to_string();
+        line.push('\n');
+        if let Err(e) = self.writer.write_all(line.as_bytes()).await {
+            if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                pending.remove(&request_id);
+            }
+            return Err(e.to_string());
+        }
+        reply_rx
+            .await
+            .unwrap_or_else(|_| Err("Connection to MPV is closed".to_owned()))
+    }
+
+    pub async fn send(&mut self, args: Vec<&str>) -> Result<(), String> {
+        self.command(json!(args)).await.map(|_| ())
+    }
+
+    pub async fn observe_property(&mut self, id: u64, property: &str) -> Result<(), String> {
+        self.command(json!(["observe_property", id, property]))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn observe_playback(&mut self) -> Result<(), String> {
+        self.observe_property(1, "time-pos").await?;
+        self.observe_property(2, "duration").await?;
+        self.observe_property(3, "pause").await?;
+        self.observe_property(4, "idle-active").await?;
+        self.observe_property(5, "volume").await?;
+        self.observe_property(6, "mute").await?;
+        self.observe_property(7, "metadata").await?;
+        self.observe_property(8, "playlist-pos").await
     }
 
     pub async fn recv(&mut self) -> std::io::Result<MpvEvent> {
-        let mut buf = String::new();
-        self.reader.read_line(&mut buf).await?;
-        let parsed = serde_json::from_str::<Value>(&buf)?;
-        Ok(match parsed["event"].as_str() {
-            Some("start-file") => MpvEvent::StartFile,
-            Some("end-file") => {
-                MpvEvent::EndFile(parsed["reason"].as_str().unwrap_or("").to_owned())
-            }
-            _ => MpvEvent::Unknown(parsed.to_string()),
-        })
+        self.events
+            .recv()
+            .await
+            .ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "Connection to MPV is closed"))
+    }
+
+    pub async fn load_song(&mut self, url: &str) -> Result<(), String> {
+        // replace mode also removes the song that was appended for later
+        self.send(vec!["loadfile", url, "replace"]).await
+    }
+
+    /// Add a song after the current one, MPV plays it when the current one ends.
+    pub async fn append_song(&mut self, url: &str) -> Result<(), String> {
+        self.send(vec!["loadfile", url, "append"]).await
+    }
+
+    /// Remove every entry of the playlist except the one being played.
+    pub async fn clear_playlist(&mut self) -> Result<(), String> {
+        self.send(vec!["playlist-clear"]).await
+    }
+
+    pub async fn remove_from_playlist(&mut self, index: i64) -> Result<(), String> {
+        self.send(vec!["playlist-remove", &index.to_string()]).await
+    }
+
+    pub async fn stop(&mut self) -> Result<(), String> {
+        self.send(vec!["stop"]).await
     }
 
-    pub async fn get_link(&self, url: &str) -> String {
-        let result = tokio::process::Command::new("youtube-dl")
-            .arg("-x")
-            .arg("--get-url")
-            .arg(url)
-            .output()
+    pub async fn play(&mut self) -> Result<(), String> {
+        self.send(vec!["playlist-play-index", "0"]).await
+    }
+
+    pub async fn pause(&mut self) -> Result<(), String> {
+        self.set_property("pause", true).await
+    }
+
+    pub async fn unpause(&mut self) -> Result<(), String> {
+        self.set_property("pause", false).await
+    }
+
+    pub async fn seek_relative(&mut self, offset: i64) -> Result<(), String> {
+        self.send(vec!["seek", &offset.to_string(), "relative"])
             .await
-            .unwrap();
-        std::str::from_utf8(result.stdout.as_slice())
-            .unwrap()
-            .to_owned()
     }
 
-    pub async fn load_song(&mut self, url: &str) {
-        let file_url = self.get_link(url).await;
-        // use replace mode because we only need 1 song in MPV at a time
-        self.send(vec!["loadfile", &file_url, "replace"]).await;
+    pub async fn seek_to(&mut self, position: Duration) -> Result<(), String> {
+        self.send(vec!["seek", &position.as_secs().to_string(), "absolute"])
+            .await
     }
 
-    pub async fn play(&mut self) {
-        self.send(vec!["playlist-play-index", "0"]).await;
+    #[allow(dead_code)]
+    pub async fn get_property<T: DeserializeOwned>(&mut self, property: &str) -> Result<T, String> {
+        let data = self.command(json!(["get_property", property])).await?;
+        serde_json::from_value(data).map_err(|e| e.to_string())
     }
 
-    pub async fn pause(&mut self) {
-        self.send(vec!["set", "pause", "yes"]).await;
+    pub async fn set_property<T: Serialize>(
+        &mut self,
+        property: &str,
+        value: T,
+    ) -> Result<(), String> {
+        self.command(json!(["set_property", property, value]))
+            .await
+            .map(|_| ())
     }
+}
 
-    pub async fn unpause(&mut self) {
-        self.send(vec!["set", "pause", "no"]).await;
+async fn read_messages(
+    mut reader: BufReader<OwnedReadHalf>,
+    pending: PendingReplies,
+    events: mpsc::UnboundedSender<MpvEvent>,
+) {
+    let mut buf = String::new();
+    while let Ok(bytes) = reader.read_line(&mut buf).await {
+        if bytes == 0 {
+            break;
+        }
+        if let Ok(parsed) = serde_json::from_str::<Value>(&buf) {
+            if parsed.get("event").is_some() {
+                if events.send(parse_event(&parsed)).is_err() {
+                    break;
+                }
+            } else if let Some(request_id) = parsed["request_id"].as_u64() {
+                let reply_tx = pending
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .and_then(|pending| pending.remove(&request_id));
+                if let Some(reply_tx) = reply_tx {
+                    _ = reply_tx.send(parse_reply(&parsed));
+                }
+            }
+        }
+        buf.clear();
+    }
+    // dropping the waiting senders wakes up every pending command with an error
+    pending.lock().unwrap().take();
+}
+
+fn parse_reply(parsed: &Value) -> Reply {
+    match parsed["error"].as_str() {
+        Some("success") => Ok(parsed["data"].clone()),
+        Some(error) => Err(error.to_owned()),
+        None => Err(format!("Unexpected reply from MPV: {}", parsed)),
+    }
+}
+
+fn parse_event(parsed: &Value) -> MpvEvent {
+    match parsed["event"].as_str() {
+        Some("start-file") => MpvEvent::StartFile,
+        Some("end-file") => MpvEvent::EndFile(parsed["reason"].as_str().unwrap_or("").to_owned()),
+        Some("property-change") => parse_property_change(parsed),
+        _ => MpvEvent::Unknown(parsed.to_string()),
     }
+}
 
-    pub async fn get_property(&mut self, property: &str) {
-        self.send(vec!["get_property", property]).await;
+fn parse_property_change(parsed: &Value) -> MpvEvent {
+    let data = &parsed["data"];
+    let seconds = data
+        .as_f64()
+        .map(|secs| Duration::from_secs_f64(secs.max(0.0)));
+    match (parsed["name"].as_str(), seconds, data.as_bool()) {
+        (Some("time-pos"), Some(position), _) => MpvEvent::TimePos(position),
+        (Some("duration"), Some(duration), _) => MpvEvent::Duration(duration),
+        (Some("pause"), _, Some(paused)) => MpvEvent::Pause(paused),
+        (Some("idle-active"), _, Some(idle)) => MpvEvent::Idle(idle),
+        (Some("volume"), _, _) if data.is_number() => {
+            MpvEvent::Volume(data.as_f64().unwrap_or_default().round() as i64)
+        }
+        (Some("mute"), _, Some(muted)) => MpvEvent::Mute(muted),
+        (Some("playlist-pos"), _, _) if data.is_i64() => {
+            MpvEvent::PlaylistPos(data.as_i64().unwrap_or_default())
+        }
+        // `null` when no file is loaded
+        (Some("metadata"), _, _) => MpvEvent::Metadata(
+            data.as_object()
+                .map(|tags| {
+                    tags.iter()
+                        .filter_map(|(key, value)| {
+                            Some((key.to_lowercase(), value.as_str()?.to_owned()))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ),
+        _ => MpvEvent::Unknown(parsed.to_string()),
     }
 }
@@ -0,0 +1,387 @@
+use crate::{
+    extractor::Extractor,
+    library::{self, Library},
+    youtube::{self, SearchPage, SearchQuery, SongEntry},
+    ytdlp,
+};
+use async_trait::async_trait;
+use std::{collections::HashMap, env, path::PathBuf, sync::Arc};
+use tokio::sync::Mutex;
+
+/// A source of songs: where to search them, how to play them and how to find
+/// more like them. Song IDs only mean something to the provider they came from.
+#[async_trait]
+pub trait Provider: Send + Sync {
+    /// Search for songs, `page_token` comes from the previous `SearchPage`.
+    async fn search(&self, query: &str, page_token: Option<&str>) -> Result<SearchPage, String>;
+    /// A URL of the audio that MPV can play.
+    async fn stream_url(&self, id: &str) -> Result<String, String>;
+    /// The song with as much metadata (duration, channel...) as available.
+    async fn metadata(&self, id: &str) -> Result<SongEntry, String>;
+    /// Songs similar to the given one, for the radio mode.
+    async fn related(&self, id: &str) -> Result<Vec<SongEntry>, String>;
+    /// Every song of a Youtube playlist.
+    async fn playlist_songs(&self, playlist_id: &str) -> Result<Vec<SongEntry>, String>;
+}
+
+/// Searches with the Youtube Data API (needs `YOUTUBE_API_KEY`) and extracts
+/// the audio stream with the extractor.
+pub struct YoutubeProvider {
+    extractor: Arc<Extractor>,
+    /// The IDs of the `channel:` filters, so the next pages of a search don't
+    /// look the channel up again.
+    channel_ids: std::sync::Mutex<HashMap<String, String>>,
+}
+
+impl YoutubeProvider {
+    async fn channel_id(&self, channel: &str) -> Result<String, String> {
+        if let Some(id) = self.channel_ids.lock().unwrap().get(channel) {
+            return Ok(id.to_owned());
+        }
+        let id = youtube::resolve_channel_id(channel).await?;
+        self.channel_ids
+            .lock()
+            .unwrap()
+            .insert(channel.to_owned(), id.clone());
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl Provider for YoutubeProvider {
+    async fn search(&self, query: &str, page_token: Option<&str>) -> Result<SearchPage, String> {
+        let query = SearchQuery::parse(query);
+        let channel_id = match &query.channel {
+            Some(channel) => Some(self.channel_id(channel).await?),
+            None => None,
+        };
+        youtube::search_song(&query, channel_id.as_deref(), page_token).await
+    }
+
+    async fn stream_url(&self, id: &str) -> Result<String, String> {
+        self.extractor.audio_url(&youtube::watch_url(id)).await
+    }
+
+    async fn metadata(&self, id: &str) -> Result<SongEntry, String> {
+        youtube::get_song(id).await
+    }
+
+    async fn related(&self, id: &str) -> Result<Vec<SongEntry>, String> {
+        youtube::similar_songs(id).await
+    }
+
+    async fn playlist_songs(&self, playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+        youtube::playlist_songs(playlist_id).await
+    }
+}
+
+/// Searches and extracts everything with yt-dlp, no API key needed.
+pub struct YtDlpProvider {
+    extractor: Arc<Extractor>,
+}
+
+#[async_trait]
+impl Provider for YtDlpProvider {
+    async fn search(&self, query: &str, page_token: Option<&str>) -> Result<SearchPage, String> {
+        ytdlp::search(query, page_token).await
+    }
+
+    async fn stream_url(&self, id: &str) -> Result<String, String> {
+        self.extractor.audio_url(&youtube::watch_url(id)).await
+    }
+
+    async fn metadata(&self, id: &str) -> Result<SongEntry, String> {
+        ytdlp::get_song(id).await
+    }
+
+    async fn related(&self, id: &str) -> Result<Vec<SongEntry>, String> {
+        ytdlp::similar_songs(id).await
+    }
+
+    async fn playlist_songs(&self, playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+        ytdlp::playlist_songs(playlist_id).await
+    }
+}
+
+/// Uses the `primary` provider, and the `fallback` one whenever it fails (no
+/// API key, exceeded quota, network error...). Both have to use the same IDs.
+pub struct FallbackProvider {
+    primary: Box<dyn Provider>,
+    fallback: Box<dyn Provider>,
+}
+
+#[async_trait]
+impl Provider for FallbackProvider {
+    async fn search(&self, query: &str, page_token: Option<&str>) -> Result<SearchPage, String> {
+        match self.primary.search(query, page_token).await {
+            Ok(page) => Ok(page),
+            Err(e) => self
+                .fallback
+                .search(query, page_token)
+                .await
+                .map_err(|fallback_error| both_failed(e, fallback_error)),
+        }
+    }
+
+    async fn stream_url(&self, id: &str) -> Result<String, String> {
+        // both providers run the same extractor, trying again would only
+        // double the wait before the error
+        self.primary.stream_url(id).await
+    }
+
+    async fn metadata(&self, id: &str) -> Result<SongEntry, String> {
+        match self.primary.metadata(id).await {
+            Ok(song) => Ok(song),
+            Err(e) => self
+                .fallback
+                .metadata(id)
+                .await
+                .map_err(|fallback_error| both_failed(e, fallback_error)),
+        }
+    }
+
+    async fn related(&self, id: &str) -> Result<Vec<SongEntry>, String> {
+        match self.primary.related(id).await {
+            Ok(songs) => Ok(songs),
+            Err(e) => self
+                .fallback
+                .related(id)
+                .await
+                .map_err(|fallback_error| both_failed(e, fallback_error)),
+        }
+    }
+
+    async fn playlist_songs(&self, playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+        match self.primary.playlist_songs(playlist_id).await {
+            Ok(songs) => Ok(songs),
+            Err(e) => self
+                .fallback
+                .playlist_songs(playlist_id)
+                .await
+                .map_err(|fallback_error| both_failed(e, fallback_error)),
+        }
+    }
+}
+
+/// Songs of the local library, played from their files. The library is
+/// scanned in the background from the start, and again when files are added
+/// or removed.
+pub struct LocalProvider {
+    dirs: Vec<PathBuf>,
+    library: Arc<Mutex<Option<Arc<Library>>>>,
+}
+
+impl LocalProvider {
+    /// Must be called on the async runtime, which runs the first scan.
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        let provider = Self {
+            dirs,
+            library: Arc::default(),
+        };
+        let (dirs, library) = (provider.dirs.clone(), provider.library.clone());
+        tokio::spawn(async move { load_library(&dirs, &library).await });
+        provider
+    }
+
+    async fn library(&self) -> Arc<Library> {
+        load_library(&self.dirs, &self.library).await
+    }
+}
+
+/// The library, scanned again first when it's outdated. Searches wait for a
+/// scan that is already running instead of starting their own.
+async fn load_library(dirs: &[PathBuf], library: &Mutex<Option<Arc<Library>>>) -> Arc<Library> {
+    let mut library = library.lock().await;
+    let previous = library.clone();
+    let dirs = dirs.to_vec();
+    let scanned = tokio::task::spawn_blocking(move || match previous {
+        Some(previous) if !previous.is_outdated(&dirs) => previous,
+        previous => Arc::new(library::scan(&dirs, previous.as_deref())),
+    })
+    .await;
+    if let Ok(scanned) = scanned {
+        *library = Some(scanned);
+    }
+    library.clone().unwrap_or_default()
+}
+
+#[async_trait]
+impl Provider for LocalProvider {
+    async fn search(&self, query: &str, _page_token: Option<&str>) -> Result<SearchPage, String> {
+        let query = SearchQuery::parse(query);
+        Ok(SearchPage {
+            songs: self.library().await.search(&query),
+            next_page_token: None,
+            ..Default::default()
+        })
+    }
+
+    async fn stream_url(&self, id: &str) -> Result<String, String> {
+        library::local_file(id)
+    }
+
+    async fn metadata(&self, id: &str) -> Result<SongEntry, String> {
+        if let Some(local) = self.library().await.get(id) {
+            return Ok(local.song.clone());
+        }
+        // a song of a playlist that isn't in the library directories
+        let path = PathBuf::from(id);
+        tokio::task::spawn_blocking(move || library::read_song(&path))
+            .await
+            .ok()
+            .flatten()
+            .map(|local| local.song)
+            .ok_or_else(|| format!("File not found: {}", id))
+    }
+
+    async fn related(&self, id: &str) -> Result<Vec<SongEntry>, String> {
+        Ok(self.library().await.related(id))
+    }
+
+    async fn playlist_songs(&self, _playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+        Err("The local library has no Youtube playlists".to_owned())
+    }
+}
+
+/// Searches the local library along with the `remote` provider, its songs
+/// come first. The other calls go to the provider the song belongs to.
+pub struct LibraryProvider {
+    local: LocalProvider,
+    remote: Box<dyn Provider>,
+}
+
+impl LibraryProvider {
+    fn provider_of(&self, id: &str) -> &dyn Provider {
+        if library::is_local_id(id) {
+            &self.local
+        } else {
+            self.remote.as_ref()
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for LibraryProvider {
+    async fn search(&self, query: &str, page_token: Option<&str>) -> Result<SearchPage, String> {
+        // the library has a single page, it comes with the first remote one
+        let local_songs = match page_token {
+            Some(_) => vec![],
+            None => self.local.search(query, None).await?.songs,
+        };
+        match self.remote.search(query, page_token).await {
+            Ok(mut page) => {
+                page.songs.splice(0..0, local_songs);
+                Ok(page)
+            }
+            Err(_) if !local_songs.is_empty() => Ok(SearchPage {
+                songs: local_songs,
+                next_page_token: None,
+                ..Default::default()
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn stream_url(&self, id: &str) -> Result<String, String> {
+        self.provider_of(id).stream_url(id).await
+    }
+
+    async fn metadata(&self, id: &str) -> Result<SongEntry, String> {
+        self.provider_of(id).metadata(id).await
+    }
+
+    async fn related(&self, id: &str) -> Result<Vec<SongEntry>, String> {
+        self.provider_of(id).related(id).await
+    }
+
+    async fn playlist_songs(&self, playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+        self.remote.playlist_songs(playlist_id).await
+    }
+}
+
+fn both_failed(error: String, fallback_error: String) -> String {
+    format!("{}, then {}", error, fallback_error)
+}
+
+/// `XAUDIO_PROVIDER=yt-dlp` only uses yt-dlp. By default, the Youtube Data API
+/// is used, with yt-dlp as a fallback. The providers share one extractor, so
+/// the stream URLs it resolved are reused by all of them. When `XAUDIO_LIBRARY`
+/// is set, the local library is searched too.
+pub fn from_config() -> Box<dyn Provider> {
+    let remote = remote_provider();
+    let dirs = library::library_dirs();
+    if dirs.is_empty() {
+        return remote;
+    }
+    Box::new(LibraryProvider {
+        local: LocalProvider::new(dirs),
+        remote,
+    })
+}
+
+fn remote_provider() -> Box<dyn Provider> {
+    let extractor = Arc::new(Extractor::default());
+    match env::var("XAUDIO_PROVIDER").as_deref() {
+        Ok("yt-dlp") | Ok("ytdlp") => Box::new(YtDlpProvider { extractor }),
+        _ => Box::new(FallbackProvider {
+            primary: Box::new(YoutubeProvider {
+                extractor: extractor.clone(),
+                channel_ids: Default::default(),
+            }),
+            fallback: Box::new(YtDlpProvider { extractor }),
+        }),
+    }
+}
+
+/// Songs kept in memory, to run the app without network access in tests.
+/// The stream URLs are `fake://<id>`.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeProvider {
+    pub songs: Vec<SongEntry>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Provider for FakeProvider {
+    async fn search(&self, query: &str, _page_token: Option<&str>) -> Result<SearchPage, String> {
+        let query = query.to_lowercase();
+        Ok(SearchPage {
+            songs: self
+                .songs
+                .iter()
+                .filter(|song| song.title.to_lowercase().contains(&query))
+                .cloned()
+                .collect(),
+            next_page_token: None,
+            ..Default::default()
+        })
+    }
+
+    async fn stream_url(&self, id: &str) -> Result<String, String> {
+        self.metadata(id)
+            .await
+            .map(|song| format!("fake://{}", song.id))
+    }
+
+    async fn metadata(&self, id: &str) -> Result<SongEntry, String> {
+        self.songs
+            .iter()
+            .find(|song| song.id == id)
+            .cloned()
+            .ok_or_else(|| format!("Song not found: {}", id))
+    }
+
+    async fn related(&self, id: &str) -> Result<Vec<SongEntry>, String> {
+        Ok(self
+            .songs
+            .iter()
+            .filter(|song| song.id != id)
+            .cloned()
+            .collect())
+    }
+
+    async fn playlist_songs(&self, _playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+        Ok(self.songs.clone())
+    }
+}
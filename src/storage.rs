@@ -0,0 +1,372 @@
+use crate::{
+    formats::{self, PlaylistFormat},
+    library,
+    utils::{unix_timestamp, HOME_DIR, MAX_VOLUME, PLAYLIST_FILE_PATH, VOLUME_FILE_PATH},
+    youtube::{SongEntry, SongSource},
+};
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io::{Error, ErrorKind, Result, Write},
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_PLAYLIST: &str = "default";
+const PLAYLIST_EXTENSION: &str = "playlist";
+const PLAYLIST_VERSION: u32 = 2;
+
+/// What's written in a `.playlist` file. The `version` is bumped whenever the
+/// format changes in a way older versions can't read.
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct PlaylistFile {
+    version: u32,
+    songs: Vec<SongEntry>,
+}
+
+/// `$XDG_DATA_HOME/xaudio`, or `~/.local/share/xaudio` when it's not set.
+pub fn data_dir() -> PathBuf {
+    let base = match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir().join(".local").join("share"),
+    };
+    base.join("xaudio")
+}
+
+/// `$XDG_CACHE_HOME/xaudio`, or `~/.cache/xaudio` when it's not set.
+pub fn cache_dir() -> PathBuf {
+    let base = match env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir().join(".cache"),
+    };
+    base.join("xaudio")
+}
+
+fn home_dir() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap_or(HOME_DIR.to_owned()))
+}
+
+fn playlists_dir() -> PathBuf {
+    data_dir().join("playlists")
+}
+
+fn playlist_path(name: &str) -> PathBuf {
+    playlists_dir().join(format!("{}.{}", name, PLAYLIST_EXTENSION))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn backup_path(name: &str) -> PathBuf {
+    with_suffix(&playlist_path(name), ".bak")
+}
+
+/// Playlist names end up as file names, so they can't contain path separators.
+pub fn validate_playlist_name(name: &str) -> std::result::Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Playlist name cannot be empty".to_owned());
+    }
+    if name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("Invalid playlist name: {}", name));
+    }
+    Ok(name.to_owned())
+}
+
+pub fn list_playlists() -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(playlists_dir())? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some(PLAYLIST_EXTENSION) {
+            if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                names.push(name.to_owned());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+pub fn read_playlist(name: &str) -> Result<Vec<SongEntry>> {
+    read_playlist_file(playlist_path(name))
+}
+
+fn read_playlist_file(path: PathBuf) -> Result<Vec<SongEntry>> {
+    let content = fs::read_to_string(path)?;
+    if !content.trim_start().starts_with('{') {
+        return Ok(parse_legacy_playlist(&content));
+    }
+    let playlist: PlaylistFile =
+        serde_json::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if playlist.version > PLAYLIST_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Playlist format v{} is not supported, please upgrade xaudio",
+                playlist.version
+            ),
+        ));
+    }
+    let mut songs = playlist.songs;
+    // version 1 had no source, local files were only told apart by their path
+    if playlist.version < 2 {
+        for song in songs
+            .iter_mut()
+            .filter(|song| library::is_local_id(&song.id))
+        {
+            song.source = SongSource::Local;
+        }
+    }
+    Ok(songs)
+}
+
+/// The format used before versioning: one `<id> - <title>` line per song.
+fn parse_legacy_playlist(content: &str) -> Vec<SongEntry> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once(" - "))
+        .map(|(id, title)| SongEntry {
+            id: id.to_owned(),
+            title: title.trim().to_owned(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Write the playlist to a temporary file first and rename it over the old
+/// one, so a crash in the middle of a save can't leave a truncated playlist.
+/// The previous version is kept next to it as `<name>.playlist.bak`.
+pub fn save_playlist(name: &str, playlist: &[SongEntry]) -> Result<()> {
+    fs::create_dir_all(playlists_dir())?;
+    let content = serde_json::to_string_pretty(&PlaylistFile {
+        version: PLAYLIST_VERSION,
+        songs: playlist.to_vec(),
+    })
+    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let path = playlist_path(name);
+    // saving the same songs again would make the backup a copy of them, and
+    // lose the previous version
+    if fs::read_to_string(&path).is_ok_and(|current| current == content) {
+        return Ok(());
+    }
+    let temp_path = with_suffix(&path, ".tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    if path.exists() {
+        fs::copy(&path, backup_path(name))?;
+    }
+    fs::rename(temp_path, path)
+}
+
+pub fn create_playlist(name: &str) -> Result<()> {
+    if playlist_path(name).exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("Playlist {} already exists", name),
+        ));
+    }
+    save_playlist(name, &[])
+}
+
+pub fn rename_playlist(name: &str, new_name: &str) -> Result<()> {
+    if playlist_path(new_name).exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("Playlist {} already exists", new_name),
+        ));
+    }
+    fs::rename(playlist_path(name), playlist_path(new_name))?;
+    if backup_path(name).exists() {
+        fs::rename(backup_path(name), backup_path(new_name))?;
+    }
+    Ok(())
+}
+
+/// The deleted playlist is kept as its backup, in case it was a mistake.
+pub fn delete_playlist(name: &str) -> Result<()> {
+    fs::rename(playlist_path(name), backup_path(name))
+}
+
+/// Expand a leading `~` so paths typed in the app work like in a shell.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.trim().strip_prefix('~') {
+        Some(rest) => home_dir().join(rest.trim_start_matches('/')),
+        None => PathBuf::from(path.trim()),
+    }
+}
+
+/// Import a playlist file into the playlist called `name`, or after the file
+/// name when `name` is `None`. Songs are appended to an existing playlist,
+/// skipping the ones it already has. Returns the playlist name, its songs and
+/// how many songs were added.
+pub fn import_playlist(path: &Path, name: Option<&str>) -> Result<(String, Vec<SongEntry>, usize)> {
+    let invalid_data = |e: String| Error::new(ErrorKind::InvalidData, e);
+    let format = PlaylistFormat::from_path(path).map_err(invalid_data)?;
+    let imported = formats::import(&fs::read_to_string(path)?, format).map_err(invalid_data)?;
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(DEFAULT_PLAYLIST)
+            .to_owned(),
+    };
+    let name = validate_playlist_name(&name).map_err(invalid_data)?;
+    let mut songs = match read_playlist(&name) {
+        Ok(songs) => songs,
+        Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e),
+    };
+    let count = songs.len();
+    let added_at = unix_timestamp();
+    for song in imported {
+        if !songs.iter().any(|entry| entry.id == song.id) {
+            songs.push(SongEntry {
+                added_at: song.added_at.or(Some(added_at)),
+                ..song
+            });
+        }
+    }
+    save_playlist(&name, &songs)?;
+    let added = songs.len() - count;
+    Ok((name, songs, added))
+}
+
+/// Export a playlist, the format is picked from the file extension. Returns
+/// the number of exported songs.
+pub fn export_playlist(name: &str, path: &Path) -> Result<usize> {
+    let invalid_data = |e: String| Error::new(ErrorKind::InvalidData, e);
+    let format = PlaylistFormat::from_path(path).map_err(invalid_data)?;
+    let songs = read_playlist(name)?;
+    fs::write(path, formats::export(&songs, format).map_err(invalid_data)?)?;
+    Ok(songs.len())
+}
+
+const MAX_SEARCH_HISTORY: usize = 200;
+
+/// Past search queries, the oldest first.
+pub fn read_search_history() -> Result<Vec<String>> {
+    let content = fs::read_to_string(data_dir().join("search-history"))?;
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_owned())
+        .collect())
+}
+
+pub fn save_search_history(history: &[String]) -> Result<()> {
+    fs::create_dir_all(data_dir())?;
+    let start = history.len().saturating_sub(MAX_SEARCH_HISTORY);
+    let mut content = history[start..].join("\n");
+    content.push('\n');
+    fs::write(data_dir().join("search-history"), content)
+}
+
+pub fn read_current_playlist_name() -> Result<String> {
+    let name = fs::read_to_string(data_dir().join("current-playlist"))?;
+    validate_playlist_name(&name).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+pub fn save_current_playlist_name(name: &str) -> Result<()> {
+    fs::create_dir_all(data_dir())?;
+    fs::write(data_dir().join("current-playlist"), name)
+}
+
+pub fn read_volume() -> Result<i64> {
+    let content = fs::read_to_string(data_dir().join("volume"))?;
+    content
+        .trim()
+        .parse::<i64>()
+        .map(|volume| volume.clamp(0, MAX_VOLUME))
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+pub fn save_volume(volume: i64) -> Result<()> {
+    fs::create_dir_all(data_dir())?;
+    fs::write(data_dir().join("volume"), volume.to_string())
+}
+
+/// Before named playlists, everything lived in `~/.xaudio-playlist` and
+/// `~/.xaudio-volume`. On the first run, copy them into the data dir, the
+/// old playlist becomes the default one. The old files are left untouched.
+pub fn migrate_legacy_files() -> Result<()> {
+    let legacy_playlist = home_dir().join(PLAYLIST_FILE_PATH.trim_start_matches('/'));
+    let has_playlists = list_playlists().is_ok_and(|names| !names.is_empty());
+    if !has_playlists && legacy_playlist.exists() {
+        save_playlist(DEFAULT_PLAYLIST, &read_playlist_file(legacy_playlist)?)?;
+    }
+    let legacy_volume = home_dir().join(VOLUME_FILE_PATH.trim_start_matches('/'));
+    if !data_dir().join("volume").exists() && legacy_volume.exists() {
+        fs::create_dir_all(data_dir())?;
+        fs::copy(legacy_volume, data_dir().join("volume"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn songs(songs: &[SongEntry]) -> Vec<(&str, &str, SongSource)> {
+        songs
+            .iter()
+            .map(|song| (song.id.as_str(), song.title.as_str(), song.source))
+            .collect()
+    }
+
+    fn read(name: &str, content: &str) -> Result<Vec<SongEntry>> {
+        let path = env::temp_dir().join(format!("xaudio-{}-{}.playlist", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        let songs = read_playlist_file(path.clone());
+        _ = fs::remove_file(path);
+        songs
+    }
+
+    #[test]
+    fn legacy_playlists_are_read() {
+        let content =
+            "dQw4w9WgXcQ - Never Gonna Give You Up\nnot a song\n9bZkp7q19f0 - Gangnam - Style \n";
+        assert_eq!(
+            songs(&read("legacy", content).unwrap()),
+            [
+                (
+                    "dQw4w9WgXcQ",
+                    "Never Gonna Give You Up",
+                    SongSource::Youtube
+                ),
+                ("9bZkp7q19f0", "Gangnam - Style", SongSource::Youtube),
+            ]
+        );
+    }
+
+    #[test]
+    fn version_1_local_paths_become_local_songs() {
+        let content = r#"{"version": 1, "songs": [
+            {"title": "Never Gonna Give You Up", "id": "dQw4w9WgXcQ"},
+            {"title": "First song", "id": "/music/01 first song.flac"}
+        ]}"#;
+        assert_eq!(
+            songs(&read("v1", content).unwrap()),
+            [
+                (
+                    "dQw4w9WgXcQ",
+                    "Never Gonna Give You Up",
+                    SongSource::Youtube
+                ),
+                ("/music/01 first song.flac", "First song", SongSource::Local),
+            ]
+        );
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let content = format!(r#"{{"version": {}, "songs": []}}"#, PLAYLIST_VERSION + 1);
+        let error = read("newer", &content).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("please upgrade xaudio"));
+    }
+}
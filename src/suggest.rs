@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use std::env;
+
+/// Completes a partial search query. The search history is always checked
+/// first, a source is only asked when the history has nothing to offer.
+#[async_trait]
+pub trait SuggestionSource: Send + Sync {
+    async fn suggest(&self, prefix: &str) -> Result<Vec<String>, String>;
+
+    /// The app doesn't ask a source that never suggests anything.
+    fn is_enabled(&self) -> bool {
+        true
+    }
+}
+
+/// Used when online suggestions are turned off, or for working offline.
+pub struct NoSuggestions;
+
+#[async_trait]
+impl SuggestionSource for NoSuggestions {
+    async fn suggest(&self, _prefix: &str) -> Result<Vec<String>, String> {
+        Ok(vec![])
+    }
+
+    fn is_enabled(&self) -> bool {
+        false
+    }
+}
+
+/// The autocomplete endpoint used by the Youtube search box.
+pub struct YoutubeSuggestions;
+
+#[async_trait]
+impl SuggestionSource for YoutubeSuggestions {
+    async fn suggest(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let url = reqwest::Url::parse_with_params(
+            "https://suggestqueries.google.com/complete/search",
+            &[("client", "firefox"), ("ds", "yt"), ("q", prefix)],
+        )
+        .map_err(|e| e.to_string())?;
+        let response = reqwest::get(url).await.map_err(|e| e.to_string())?;
+        // the response looks like ["prefix", ["suggestion 1", "suggestion 2"]]
+        let result = response.json::<Value>().await.map_err(|e| e.to_string())?;
+        Ok(result[1]
+            .as_array()
+            .map(|suggestions| {
+                suggestions
+                    .iter()
+                    .filter_map(|suggestion| suggestion.as_str().map(|s| s.to_owned()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+/// Online suggestions are opt-in with `XAUDIO_SEARCH_SUGGESTIONS=true`, since
+/// they send every keystroke of the search box to Google.
+pub fn suggestion_source() -> Box<dyn SuggestionSource> {
+    match env::var("XAUDIO_SEARCH_SUGGESTIONS").as_deref() {
+        Ok("true") | Ok("1") => Box::new(YoutubeSuggestions),
+        _ => Box::new(NoSuggestions),
+    }
+}
+
+/// Whether the suggestion continues what was typed, ignoring the case.
+pub fn is_completion(suggestion: &str, prefix: &str) -> bool {
+    suggestion.chars().count() > prefix.chars().count()
+        && suggestion
+            .to_lowercase()
+            .starts_with(&prefix.to_lowercase())
+}
+
+/// The most recent query from the history that completes the prefix.
+pub fn suggest_from_history(history: &[String], prefix: &str) -> Option<String> {
+    history
+        .iter()
+        .rev()
+        .find(|query| is_completion(query, prefix))
+        .cloned()
+}
@@ -1,28 +1,69 @@
 use pancurses::{
-    curs_set, endwin, half_delay, has_colors, initscr, noecho, raw, start_color,
-    use_default_colors, Input, Window,
+    cbreak, curs_set, endwin, has_colors, initscr, noecho, raw, start_color, use_default_colors,
+    Input, Window,
 };
+use std::panic;
 use tokio::sync::mpsc::Receiver;
 
+/// How long to wait for a key before rendering again, in milliseconds.
+const INPUT_TIMEOUT: i32 = 200;
+
+/// Whether this character can be part of a pasted text. Line breaks and tabs
+/// are, the other control characters (Backspace, Ctrl-W...) are always keys.
+fn is_pasted_char(ch: char) -> bool {
+    !ch.is_control() || matches!(ch, '\n' | '\r' | '\t')
+}
+
+/// Read the inputs that are already waiting after `first`, to detect a paste.
+/// Returns them with the input that ended the burst, if any.
+fn read_burst(
+    first: Input,
+    mut next: impl FnMut() -> Option<Input>,
+) -> (Vec<Input>, Option<Input>) {
+    let mut inputs = vec![first];
+    if !matches!(first, Input::Character(ch) if is_pasted_char(ch)) {
+        return (inputs, None);
+    }
+    while let Some(input) = next() {
+        match input {
+            Input::Character(ch) if is_pasted_char(ch) => inputs.push(input),
+            _ => return (inputs, Some(input)),
+        }
+    }
+    (inputs, None)
+}
+
 pub trait App {
     type Msg;
     fn init(&mut self, win: &Window);
     fn update(&mut self, win: &Window, msg: Self::Msg) -> bool;
     fn input(&mut self, input: Input) -> Self::Msg;
+    /// Called when several characters arrive at once, which means they were
+    /// pasted. The text can contain line breaks and tabs, but no other control
+    /// characters. Return `None` to handle them as separate key presses.
+    fn paste(&mut self, text: String) -> Option<Self::Msg>;
     fn render(&self, win: &Window);
 }
 
 pub fn run<T>(app: impl App + App<Msg = T>, raw_mode: bool, mut rx: Receiver<T>) {
     let mut app = app;
 
+    // restore the terminal before the panic message gets printed
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        endwin();
+        default_hook(info);
+    }));
+
     let window = initscr();
     if raw_mode {
         raw();
+    } else {
+        cbreak();
     }
     curs_set(0);
-    half_delay(2);
     noecho();
-    window.nodelay(true);
+    window.timeout(INPUT_TIMEOUT);
     window.keypad(true);
 
     if has_colors() {
@@ -32,21 +73,82 @@ pub fn run<T>(app: impl App + App<Msg = T>, raw_mode: bool, mut rx: Receiver<T>)
 
     app.init(&window);
 
-    loop {
+    let mut next_input = None;
+    'main: loop {
         app.render(&window);
-        match window.getch() {
-            Some(input) => {
-                let msg = app.input(input);
+        let mut inputs = vec![];
+        if let Some(input) = next_input.take().or_else(|| window.getch()) {
+            window.timeout(0);
+            (inputs, next_input) = read_burst(input, || window.getch());
+            window.timeout(INPUT_TIMEOUT);
+        }
+        if inputs.len() > 1 {
+            let text = inputs
+                .iter()
+                .filter_map(|input| match input {
+                    Input::Character(ch) => Some(*ch),
+                    _ => None,
+                })
+                .collect::<String>();
+            if let Some(msg) = app.paste(text) {
+                inputs.clear();
                 if !app.update(&window, msg) {
                     break;
                 }
             }
-            None => (),
+        }
+        for input in inputs {
+            let msg = app.input(input);
+            if !app.update(&window, msg) {
+                break 'main;
+            }
         }
         while let Ok(msg) = rx.try_recv() {
-            app.update(&window, msg);
+            if !app.update(&window, msg) {
+                break 'main;
+            }
         }
     }
 
     endwin();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn burst(first: Input, rest: &[Input]) -> (Vec<Input>, Option<Input>) {
+        let mut rest = rest.iter().copied();
+        read_burst(first, || rest.next())
+    }
+
+    fn chars(text: &str) -> Vec<Input> {
+        text.chars().map(Input::Character).collect()
+    }
+
+    #[test]
+    fn a_pasted_line_break_stays_in_the_text() {
+        let (inputs, next) = burst(Input::Character('f'), &chars("oo\nbar"));
+        assert_eq!(inputs, chars("foo\nbar"));
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn a_lone_enter_is_a_key() {
+        assert_eq!(burst(Input::Character('\n'), &[]), (chars("\n"), None));
+    }
+
+    #[test]
+    fn other_control_characters_end_the_burst() {
+        let (inputs, next) = burst(
+            Input::Character('a'),
+            &[Input::Character('b'), Input::Character('\u{7f}')],
+        );
+        assert_eq!(inputs, chars("ab"));
+        assert_eq!(next, Some(Input::Character('\u{7f}')));
+
+        let (inputs, next) = burst(Input::KeyBackspace, &chars("ab"));
+        assert_eq!(inputs, [Input::KeyBackspace]);
+        assert_eq!(next, None);
+    }
+}
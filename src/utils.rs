@@ -1,37 +1,54 @@
-use crate::youtube::SongEntry;
+use crate::editor::char_width;
 use rand::prelude::*;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader, Write},
-    time::Duration,
-};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use unicode_width::UnicodeWidthStr;
 
 pub const BACKSPACE_KEY: char = '\u{7f}';
 pub const ESCAPE_KEY: char = '\u{1b}';
 pub const ENTER_KEY: char = '\n';
 pub const TAB_KEY: char = '\t';
+pub const CTRL_A_KEY: char = '\u{1}';
+pub const CTRL_B_KEY: char = '\u{2}';
+pub const CTRL_D_KEY: char = '\u{4}';
+pub const CTRL_E_KEY: char = '\u{5}';
+pub const CTRL_F_KEY: char = '\u{6}';
+pub const CTRL_H_KEY: char = '\u{8}';
+pub const CTRL_U_KEY: char = '\u{15}';
+pub const CTRL_W_KEY: char = '\u{17}';
 pub const TITLE_PADDING: usize = 12;
+pub const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
 pub const HOME_DIR: &str = env!("HOME");
+// legacy locations, migrated to the data dir on first run
 pub const PLAYLIST_FILE_PATH: &str = "/.xaudio-playlist";
+pub const VOLUME_FILE_PATH: &str = "/.xaudio-volume";
+pub const VOLUME_STEP: i64 = 5;
+pub const MAX_VOLUME: i64 = 100;
 
+/// Cut the text to `len` screen columns (wide characters take two) and add
+/// an ellipsis when something was cut.
 pub fn truncate(text: &str, len: usize) -> String {
-    let char_count = text.chars().count();
-    if len > char_count {
+    if len > text.width() {
         return text.to_owned();
-    } else {
-        return text.chars().take(len).collect::<String>() + "…";
     }
+    let mut width = 0;
+    text.chars()
+        .take_while(|ch| {
+            width += char_width(*ch);
+            width <= len
+        })
+        .collect::<String>()
+        + "…"
 }
 
 pub fn get_total_pages(len: usize, page_size: usize) -> usize {
-    len / page_size + if len % page_size == 0 { 0 } else { 1 }
+    len / page_size + if len.is_multiple_of(page_size) { 0 } else { 1 }
 }
 
-pub fn paginate<'a, T>(list: &'a [T], page: usize, page_size: usize) -> Option<&'a [T]> {
+pub fn paginate<T>(list: &[T], page: usize, page_size: usize) -> Option<&[T]> {
     let start = page * page_size;
     if start < list.len() {
         let end = start + page_size;
-        return Some(if &list[start..].len() < &page_size {
+        return Some(if list[start..].len() < page_size {
             &list[start..]
         } else {
             &list[start..end]
@@ -47,37 +64,16 @@ pub fn display_time(dur: Duration) -> String {
     format!("{:02}:{:02}:{:02}", hrs, min, sec)
 }
 
-pub fn read_playlist() -> std::io::Result<Vec<SongEntry>> {
-    let file_name = format!("{}{}", HOME_DIR, PLAYLIST_FILE_PATH);
-    let file = File::open(file_name)?;
-    let mut reader = BufReader::new(file);
-    let mut line = String::new();
-    let mut result = vec![];
-    while let Ok(bytes) = reader.read_line(&mut line) {
-        if bytes == 0 {
-            break;
-        }
-        if let Some((id, title)) = line.split_once(" - ") {
-            result.push(SongEntry {
-                id: id.to_owned(),
-                title: title.trim().to_owned(),
-            });
-        }
-        line.clear();
+pub fn parse_time(input: &str) -> Option<Duration> {
+    let parts = input.trim().split(':').collect::<Vec<&str>>();
+    if parts.len() > 3 {
+        return None;
     }
-    Ok(result)
-}
-
-pub fn save_playlist(playlist: &[SongEntry]) -> std::io::Result<()> {
-    let file_name = format!("{}{}", HOME_DIR, PLAYLIST_FILE_PATH);
-    let mut file = File::create(file_name)?;
-    playlist
-        .iter()
-        .map(|song| format!("{} - {}", song.id, song.title))
-        .for_each(|line| {
-            _ = writeln!(file, "{}", line);
-        });
-    Ok(())
+    let mut secs = 0;
+    for part in parts {
+        secs = secs * 60 + part.trim().parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(secs))
 }
 
 pub fn create_index_queue(len: usize, shuffle: bool) -> Vec<usize> {
@@ -86,5 +82,123 @@ pub fn create_index_queue(len: usize, shuffle: bool) -> Vec<usize> {
     if shuffle {
         ret.shuffle(&mut rng);
     }
-    return ret;
+    ret
+}
+
+/// Add a newly added song to an existing play queue without touching the songs
+/// that already have been played. In shuffle mode, the song lands at a random
+/// position among the songs that are yet to be played.
+pub fn add_to_index_queue(queue: &mut Vec<usize>, index: usize, played: usize, shuffle: bool) {
+    let start = (played + 1).min(queue.len());
+    let position = if shuffle {
+        rand::thread_rng().gen_range(start..=queue.len())
+    } else {
+        queue.len()
+    };
+    queue.insert(position, index);
+}
+
+/// Remove a song from the play queue, shifting the indexes of the songs after it.
+/// Returns the new position of the currently played song in the queue.
+pub fn remove_from_index_queue(queue: &mut Vec<usize>, index: usize, played: usize) -> usize {
+    let mut played = played;
+    if let Some(position) = queue.iter().position(|&i| i == index) {
+        queue.remove(position);
+        if position < played {
+            played -= 1;
+        }
+    }
+    queue
+        .iter_mut()
+        .filter(|i| **i > index)
+        .for_each(|i| *i -= 1);
+    played.min(queue.len().saturating_sub(1))
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// Truncate or pad the text with spaces so it takes exactly `width` columns.
+pub fn fit(text: &str, width: usize) -> String {
+    let text = if text.width() > width {
+        truncate(text, width.saturating_sub(1))
+    } else {
+        text.to_owned()
+    };
+    let padding = width.saturating_sub(text.width());
+    text + &" ".repeat(padding)
+}
+
+/// Short duration for lists, like `3:45` or `1:02:03`.
+pub fn display_duration(dur: Duration) -> String {
+    let sec = dur.as_secs() % 60;
+    let min = (dur.as_secs() / 60) % 60;
+    let hrs = (dur.as_secs() / 60) / 60;
+    if hrs > 0 {
+        format!("{}:{:02}:{:02}", hrs, min, sec)
+    } else {
+        format!("{}:{:02}", min, sec)
+    }
+}
+
+/// Compact count like `950`, `12K` or `3.4M`.
+pub fn display_count(count: u64) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=9_999 => format!("{:.1}K", count as f64 / 1e3),
+        10_000..=999_999 => format!("{}K", count / 1_000),
+        1_000_000..=9_999_999 => format!("{:.1}M", count as f64 / 1e6),
+        10_000_000..=999_999_999 => format!("{}M", count / 1_000_000),
+        _ => format!("{:.1}B", count as f64 / 1e9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_time_reads_seconds_minutes_and_hours() {
+        assert_eq!(parse_time("45"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_time("1:30"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_time(" 1 : 02:03 "), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_time("90:00"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("1:"), None);
+        assert_eq!(parse_time("1:30s"), None);
+        assert_eq!(parse_time("1:00:00:00"), None);
+    }
+
+    #[test]
+    fn remove_from_index_queue_shifts_the_indexes() {
+        // playing the third song of the queue, which is song 3
+        let mut queue = vec![2, 0, 3, 1];
+        let played = remove_from_index_queue(&mut queue, 0, 2);
+        assert_eq!(queue, [1, 2, 0]);
+        assert_eq!(queue[played], 2);
+
+        let played = remove_from_index_queue(&mut queue, 0, played);
+        assert_eq!(queue, [0, 1]);
+        assert_eq!(queue[played], 1);
+    }
+
+    #[test]
+    fn remove_from_index_queue_keeps_the_position_in_the_queue() {
+        let mut queue = vec![0, 1, 2];
+        assert_eq!(remove_from_index_queue(&mut queue, 2, 0), 0);
+        assert_eq!(queue, [0, 1]);
+        // the played song is removed, the next one takes its place
+        assert_eq!(remove_from_index_queue(&mut queue, 0, 0), 0);
+        assert_eq!(queue, [0]);
+        // the last song is removed, the position stays in the queue
+        let mut queue = vec![0, 1, 2];
+        assert_eq!(remove_from_index_queue(&mut queue, 2, 2), 1);
+        assert_eq!(queue, [0, 1]);
+        assert_eq!(remove_from_index_queue(&mut queue, 5, 1), 1);
+        assert_eq!(queue, [0, 1]);
+    }
 }
@@ -1,20 +1,38 @@
 use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
-use std::{env, time::Duration};
+use std::{collections::HashMap, env, time::Duration};
 
 fn get_api_key() -> Result<String, String> {
-    return env::var("YOUTUBE_API_KEY").map_err(stringify_error);
+    env::var("YOUTUBE_API_KEY").map_err(stringify_error)
 }
 
 fn stringify_error(e: impl std::fmt::Debug + std::fmt::Display) -> String {
     format!("{}", e)
 }
 
+/// GET an API endpoint. Error responses, like an exceeded quota, become an
+/// `Err` with the message from the API.
+async fn api_get<T: DeserializeOwned>(url: impl reqwest::IntoUrl) -> Result<T, String> {
+    let response = reqwest::get(url).await.map_err(stringify_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.json::<Value>().await.unwrap_or_default();
+        let message = body["error"]["message"]
+            .as_str()
+            .map(|message| message.to_owned())
+            .unwrap_or_else(|| status.to_string());
+        return Err(format!("Youtube API error: {}", message));
+    }
+    response.json::<T>().await.map_err(stringify_error)
+}
+
 #[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct YoutubeSearchResult {
     pub kind: String,
     pub etag: String,
+    #[serde(default)]
     pub next_page_token: String,
     pub region_code: String,
     pub page_info: PageInfo,
@@ -48,93 +66,529 @@ pub struct Snippet {
     pub title: String,
     pub description: String,
     pub channel_title: String,
+    #[serde(default)]
+    pub live_broadcast_content: String,
+}
+
+/// Where a song is played from, which gives the meaning of its `id`.
+#[derive(
+    Default, Debug, Clone, Copy, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SongSource {
+    /// The `id` is a Youtube video ID.
+    #[default]
+    Youtube,
+    /// The `id` is the URL of a page or a media file, played by MPV.
+    Url,
+    /// The `id` is the absolute path of a file of the local library.
+    Local,
+    /// The `id` is the URL of an internet radio stream.
+    Stream,
 }
 
 #[derive(Default, Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct SongEntry {
     pub title: String,
     pub id: String,
+    #[serde(default)]
+    pub source: SongSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Length of the song in seconds, known after it has been played once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// Unix timestamp of when the song was added to the playlist.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<u64>,
+    #[serde(default)]
+    pub play_count: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view_count: Option<u64>,
 }
 
-pub async fn search_song(input: &str) -> Result<Vec<SongEntry>, String> {
-    let key = get_api_key()?;
-    let url = format!("https://youtube.googleapis.com/youtube/v3/search?part=snippet&order=relevance&q={}&type=video&key={}&maxResults=50", input, key);
-    let response = reqwest::get(&url).await.map_err(stringify_error)?;
-    if let Ok(result) = response.json::<YoutubeSearchResult>().await {
-        let entries = result
-            .items
-            .into_iter()
-            .filter(|item| item.snippet.is_some())
-            .map(|item| {
-                let snippet = item.snippet.unwrap();
-                SongEntry {
-                    title: snippet.title.to_owned(),
-                    id: item.id.video_id.to_owned(),
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistItemsResult {
+    #[serde(default)]
+    pub next_page_token: String,
+    pub items: Vec<PlaylistItem>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistItem {
+    pub snippet: Option<PlaylistItemSnippet>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaylistItemSnippet {
+    pub title: String,
+    #[serde(default)]
+    pub video_owner_channel_title: String,
+    pub resource_id: ResourceId,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceId {
+    pub kind: String,
+    #[serde(default)]
+    pub video_id: String,
+}
+
+fn song_from_item(item: Item) -> Option<SongEntry> {
+    let snippet = item.snippet?;
+    Some(SongEntry {
+        title: snippet.title,
+        id: item.id.video_id,
+        channel: Some(snippet.channel_title).filter(|channel| !channel.is_empty()),
+        ..Default::default()
+    })
+}
+
+/// One page of search results, with the token to fetch the next one (if
+/// there are more results).
+#[derive(Default, Debug, Clone)]
+pub struct SearchPage {
+    pub songs: Vec<SongEntry>,
+    pub next_page_token: Option<String>,
+    /// Something to tell the user about the results, like a filter that
+    /// could not be applied.
+    pub notice: Option<String>,
+}
+
+/// A search input split into the keywords and the filters, for example
+/// `lofi dur:long after:2021 live:no`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub keywords: String,
+    /// `dur:short|medium|long`, the API's `videoDuration`
+    pub duration: Option<String>,
+    /// `after:YYYY[-MM[-DD]]`, the API's `publishedAfter`
+    pub published_after: Option<String>,
+    /// `channel:<name or channel ID>`, the API's `channelId`
+    pub channel: Option<String>,
+    /// `live:yes` only searches live streams (`eventType=live`), `live:no`
+    /// removes them from the results, which the API can't do by itself
+    pub live: Option<bool>,
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Self {
+        let mut query = Self::default();
+        let mut keywords = vec![];
+        for word in input.split_whitespace() {
+            match word.split_once(':') {
+                Some(("dur", duration)) if ["short", "medium", "long"].contains(&duration) => {
+                    query.duration = Some(duration.to_owned());
+                }
+                Some(("after", date)) if parse_date(date).is_some() => {
+                    query.published_after = Some(date.to_owned());
+                }
+                Some(("channel", channel)) if !channel.is_empty() => {
+                    query.channel = Some(channel.to_owned());
                 }
-            })
-            .collect();
-        return Ok(entries);
+                Some(("live", live)) if ["yes", "no"].contains(&live) => {
+                    query.live = Some(live == "yes");
+                }
+                _ => keywords.push(word),
+            }
+        }
+        query.keywords = keywords.join(" ");
+        query
     }
-    Ok(vec![])
+
+    /// The filters in the same syntax as the input, for the search header.
+    pub fn filters(&self) -> String {
+        let mut filters = vec![];
+        if let Some(duration) = &self.duration {
+            filters.push(format!("dur:{}", duration));
+        }
+        if let Some(date) = &self.published_after {
+            filters.push(format!("after:{}", date));
+        }
+        if let Some(channel) = &self.channel {
+            filters.push(format!("channel:{}", channel));
+        }
+        match self.live {
+            Some(true) => filters.push("live:yes".to_owned()),
+            Some(false) => filters.push("live:no".to_owned()),
+            None => {}
+        }
+        filters.join(" ")
+    }
+}
+
+/// Turn `YYYY`, `YYYY-MM` or `YYYY-MM-DD` into the RFC 3339 time the API wants.
+pub fn parse_date(date: &str) -> Option<String> {
+    let re = Regex::new(r"^(\d{4})(?:-(\d{2}))?(?:-(\d{2}))?$").unwrap();
+    let captures = re.captures(date)?;
+    let part = |index: usize| captures.get(index).map_or("01", |m| m.as_str());
+    Some(format!(
+        "{}-{}-{}T00:00:00Z",
+        &captures[1],
+        part(2),
+        part(3)
+    ))
+}
+
+/// Channel IDs are used as they are, anything else is searched as a channel
+/// name and the first match is used.
+pub async fn resolve_channel_id(channel: &str) -> Result<String, String> {
+    let re = Regex::new(r"^UC[A-Za-z0-9_-]{22}$").unwrap();
+    if re.is_match(channel) {
+        return Ok(channel.to_owned());
+    }
+    let key = get_api_key()?;
+    let url = reqwest::Url::parse_with_params(
+        "https://youtube.googleapis.com/youtube/v3/search",
+        &[
+            ("part", "snippet"),
+            ("type", "channel"),
+            ("q", channel),
+            ("key", &key),
+            ("maxResults", "1"),
+        ],
+    )
+    .map_err(stringify_error)?;
+    let result = api_get::<Value>(url).await?;
+    result["items"][0]["id"]["channelId"]
+        .as_str()
+        .map(|id| id.to_owned())
+        .ok_or_else(|| format!("Cannot find channel {}", channel))
+}
+
+/// Search for videos, `channel_id` is the resolved `channel:` filter of the
+/// query.
+pub async fn search_song(
+    query: &SearchQuery,
+    channel_id: Option<&str>,
+    page_token: Option<&str>,
+) -> Result<SearchPage, String> {
+    let key = get_api_key()?;
+    let mut params = vec![
+        ("part", "snippet".to_owned()),
+        ("order", "relevance".to_owned()),
+        ("q", query.keywords.to_owned()),
+        ("type", "video".to_owned()),
+        ("key", key.to_owned()),
+        ("maxResults", "50".to_owned()),
+        ("pageToken", page_token.unwrap_or_default().to_owned()),
+    ];
+    if let Some(duration) = &query.duration {
+        params.push(("videoDuration", duration.to_owned()));
+    }
+    if let Some(date) = query.published_after.as_deref().and_then(parse_date) {
+        params.push(("publishedAfter", date));
+    }
+    if let Some(channel_id) = channel_id {
+        params.push(("channelId", channel_id.to_owned()));
+    }
+    if query.live == Some(true) {
+        params.push(("eventType", "live".to_owned()));
+    }
+    let url = reqwest::Url::parse_with_params(
+        "https://youtube.googleapis.com/youtube/v3/search",
+        &params,
+    )
+    .map_err(stringify_error)?;
+    let result = api_get::<YoutubeSearchResult>(url).await?;
+    let mut songs = result
+        .items
+        .into_iter()
+        .filter(|item| match (&item.snippet, query.live) {
+            (Some(snippet), Some(false)) => {
+                !["live", "upcoming"].contains(&snippet.live_broadcast_content.as_str())
+            }
+            _ => true,
+        })
+        .filter_map(song_from_item)
+        .collect::<Vec<SongEntry>>();
+    add_video_details(&mut songs).await;
+    Ok(SearchPage {
+        songs,
+        next_page_token: Some(result.next_page_token).filter(|token| !token.is_empty()),
+        ..Default::default()
+    })
 }
 
 pub async fn similar_songs(id: &str) -> Result<Vec<SongEntry>, String> {
     let key = get_api_key()?;
     let url = format!("https://youtube.googleapis.com/youtube/v3/search?part=snippet&order=relevance&type=video&key={}&maxResults=30&relatedToVideoId={}", key, id);
-    let response = reqwest::get(&url).await.map_err(stringify_error)?;
-    if let Ok(result) = response.json::<YoutubeSearchResult>().await {
-        let entries = result
-            .items
-            .into_iter()
-            .filter(|item| item.snippet.is_some())
-            .map(|item| {
-                let snippet = item.snippet.unwrap();
-                SongEntry {
-                    title: snippet.title.to_owned(),
-                    id: item.id.video_id.to_owned(),
-                }
-            })
-            .collect();
-        return Ok(entries);
-    }
-    Ok(vec![])
+    let result = api_get::<YoutubeSearchResult>(&url).await?;
+    Ok(result
+        .items
+        .into_iter()
+        .filter_map(song_from_item)
+        .collect())
+}
+
+/// Duration and view count of a video, from the `videos` endpoint.
+#[derive(Default, Debug, Clone)]
+pub struct VideoDetails {
+    pub title: String,
+    pub channel: String,
+    pub duration: Duration,
+    pub view_count: Option<u64>,
 }
 
-pub async fn get_song_duration(id: &str) -> Result<Duration, String> {
+/// Fetch the details of up to 50 videos with a single request.
+pub async fn video_details(ids: &[String]) -> Result<HashMap<String, VideoDetails>, String> {
     let key = get_api_key()?;
-    let url = format!("https://youtube.googleapis.com/youtube/v3/videos?id={}&part=contentDetails&key={}&maxResults=30", id, key);
-    let response = reqwest::get(&url).await.map_err(stringify_error)?;
-    if let Ok(result) = response.json::<Value>().await {
-        let duration = result["items"]
-            .as_array()
-            .unwrap()
-            .get(0)
-            .unwrap()
-            .as_object()
-            .unwrap()
-            .get("contentDetails")
-            .unwrap()
-            .get("duration")
-            .unwrap()
-            .as_str()
-            .unwrap();
-        // Shamelessly stolen from https://gist.github.com/vkdinventor/93a112366a68f7eb6135e57f287687e5
-        let re = Regex::new(r"^PT(?:(\d+)H)?(?:(\d+)M)?(?:(\d+))S?$").unwrap();
-        if let Some(captures) = re.captures(duration) {
-            if captures.len() >= 3 {
-                let hrs = captures
-                    .get(1)
-                    .map_or(0, |m| m.as_str().parse::<u64>().unwrap());
-                let min = captures
-                    .get(2)
-                    .map_or(0, |m| m.as_str().parse::<u64>().unwrap());
-                let sec = captures
-                    .get(3)
-                    .map_or(0, |m| m.as_str().parse::<u64>().unwrap());
-                return Ok(Duration::from_secs(sec + min * 60 + hrs * 60 * 60));
+    let url = format!("https://youtube.googleapis.com/youtube/v3/videos?id={}&part=snippet,contentDetails,statistics&key={}&maxResults=50", ids.join(","), key);
+    let result = api_get::<Value>(&url).await?;
+    let items = result["items"]
+        .as_array()
+        .ok_or_else(|| "Cannot get video details".to_owned())?;
+    Ok(items
+        .iter()
+        .filter_map(|item| {
+            let id = item["id"].as_str()?.to_owned();
+            let details = VideoDetails {
+                title: item["snippet"]["title"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_owned(),
+                channel: item["snippet"]["channelTitle"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_owned(),
+                duration: item["contentDetails"]["duration"]
+                    .as_str()
+                    .map(parse_duration)
+                    .unwrap_or_default(),
+                // the count is a string in the API response
+                view_count: item["statistics"]["viewCount"]
+                    .as_str()
+                    .and_then(|count| count.parse::<u64>().ok()),
+            };
+            Some((id, details))
+        })
+        .collect())
+}
+
+/// Fill in the duration and view count of the songs, leaving them empty if
+/// the details can't be fetched.
+pub async fn add_video_details(songs: &mut [SongEntry]) {
+    let ids = songs
+        .iter()
+        .map(|song| song.id.to_owned())
+        .collect::<Vec<String>>();
+    for (chunk, ids) in songs.chunks_mut(50).zip(ids.chunks(50)) {
+        let Ok(details) = video_details(ids).await else {
+            return;
+        };
+        for song in chunk.iter_mut() {
+            if let Some(details) = details.get(&song.id) {
+                song.duration = Some(details.duration.as_secs()).filter(|secs| *secs > 0);
+                song.view_count = details.view_count;
             }
         }
-        return Ok(Duration::default());
     }
-    Err("Cannot get song".to_owned())
+}
+
+pub async fn get_song(id: &str) -> Result<SongEntry, String> {
+    let details = video_details(&[id.to_owned()])
+        .await?
+        .remove(id)
+        .ok_or_else(|| "Cannot get song".to_owned())?;
+    Ok(SongEntry {
+        title: details.title,
+        id: id.to_owned(),
+        channel: Some(details.channel).filter(|channel| !channel.is_empty()),
+        duration: Some(details.duration.as_secs()).filter(|secs| *secs > 0),
+        view_count: details.view_count,
+        ..Default::default()
+    })
+}
+
+/// Parse an ISO 8601 duration like `PT1H2M3S`.
+fn parse_duration(duration: &str) -> Duration {
+    // Shamelessly stolen from https://gist.github.com/vkdinventor/93a112366a68f7eb6135e57f287687e5
+    let re = Regex::new(r"^PT(?:(\d+)H)?(?:(\d+)M)?(?:(\d+)S)?$").unwrap();
+    if let Some(captures) = re.captures(duration) {
+        let part = |index: usize| {
+            captures
+                .get(index)
+                .map_or(0, |m| m.as_str().parse::<u64>().unwrap_or_default())
+        };
+        return Duration::from_secs(part(3) + part(2) * 60 + part(1) * 60 * 60);
+    }
+    Duration::default()
+}
+
+/// Accept either a playlist ID (`PL...` for the playlists of users, `OL...` for
+/// albums, `UU...` for the uploads of a channel, `FL...` for favorites) or a
+/// Youtube link with a `list=` parameter.
+pub fn playlist_id_from_url(input: &str) -> Option<String> {
+    let input = input.trim();
+    let list_re = Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+    if let Ok(url) = reqwest::Url::parse(input) {
+        let host = url.host_str()?;
+        let is_youtube = ["youtube.com", "youtu.be"]
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)));
+        if !is_youtube {
+            return None;
+        }
+        return url
+            .query_pairs()
+            .find(|(name, _)| name == "list")
+            .map(|(_, list)| list.into_owned())
+            .filter(|list| list_re.is_match(list));
+    }
+    let id_re = Regex::new(r"^(?:PL|OL|UU|FL)[A-Za-z0-9_-]{10,}$").unwrap();
+    id_re.is_match(input).then(|| input.to_owned())
+}
+
+/// Fetch every video of a playlist, following `nextPageToken` until the last
+/// page. Deleted and private videos, which have no owner channel, are skipped.
+pub async fn playlist_songs(playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+    let key = get_api_key()?;
+    let mut songs = vec![];
+    let mut page_token = String::new();
+    loop {
+        let url = format!("https://youtube.googleapis.com/youtube/v3/playlistItems?part=snippet&playlistId={}&key={}&maxResults=50&pageToken={}", playlist_id, key, page_token);
+        let result = api_get::<PlaylistItemsResult>(&url).await?;
+        songs.extend(
+            result
+                .items
+                .into_iter()
+                .filter_map(|item| item.snippet)
+                .filter(|snippet| {
+                    snippet.resource_id.kind == "youtube#video"
+                        && !snippet.video_owner_channel_title.is_empty()
+                })
+                .map(|snippet| SongEntry {
+                    title: snippet.title,
+                    id: snippet.resource_id.video_id,
+                    channel: Some(snippet.video_owner_channel_title),
+                    ..Default::default()
+                }),
+        );
+        if result.next_page_token.is_empty() {
+            break;
+        }
+        page_token = result.next_page_token;
+    }
+    Ok(songs)
+}
+
+pub fn watch_url(id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={}", id)
+}
+
+impl SongEntry {
+    /// A link or path other players understand.
+    pub fn location(&self) -> String {
+        match self.source {
+            SongSource::Youtube => watch_url(&self.id),
+            SongSource::Url | SongSource::Local | SongSource::Stream => self.id.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_splits_the_filters_from_the_keywords() {
+        let query =
+            SearchQuery::parse("lofi  dur:long hip after:2021-06 channel:Chill live:no hop");
+        assert_eq!(
+            query,
+            SearchQuery {
+                keywords: "lofi hip hop".to_owned(),
+                duration: Some("long".to_owned()),
+                published_after: Some("2021-06".to_owned()),
+                channel: Some("Chill".to_owned()),
+                live: Some(false),
+            }
+        );
+        assert_eq!(
+            query.filters(),
+            "dur:long after:2021-06 channel:Chill live:no"
+        );
+    }
+
+    #[test]
+    fn parse_keeps_words_that_are_not_filters() {
+        let query = SearchQuery::parse("queen live aid -live dur:forever after:june channel:");
+        assert_eq!(
+            query.keywords,
+            "queen live aid -live dur:forever after:june channel:"
+        );
+        assert_eq!(query.filters(), "");
+        assert_eq!(SearchQuery::parse("live:yes").live, Some(true));
+    }
+
+    #[test]
+    fn playlist_id_from_links_and_ids() {
+        let id = "PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf";
+        assert_eq!(
+            playlist_id_from_url(&format!("https://www.youtube.com/playlist?list={}", id))
+                .as_deref(),
+            Some(id)
+        );
+        assert_eq!(
+            playlist_id_from_url(&format!(
+                "https://music.youtube.com/watch?v=dQw4w9WgXcQ&list={}&index=2",
+                id
+            ))
+            .as_deref(),
+            Some(id)
+        );
+        assert_eq!(
+            playlist_id_from_url(&format!("  {} ", id)).as_deref(),
+            Some(id)
+        );
+        assert_eq!(
+            playlist_id_from_url("OLAK5uy_kDeMBKWdX8tW5rZqN8hGz7y7l3zOz2t1s").as_deref(),
+            Some("OLAK5uy_kDeMBKWdX8tW5rZqN8hGz7y7l3zOz2t1s")
+        );
+        assert_eq!(
+            playlist_id_from_url("UUuAXFkgsw1L7xaCfnd5JJOw").as_deref(),
+            Some("UUuAXFkgsw1L7xaCfnd5JJOw")
+        );
+    }
+
+    #[test]
+    fn playlist_id_rejects_other_input() {
+        // a video ID, a search and links without a playlist
+        assert_eq!(playlist_id_from_url("dQw4w9WgXcQ"), None);
+        assert_eq!(playlist_id_from_url("lofi_hip_hop_radio"), None);
+        assert_eq!(
+            playlist_id_from_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            None
+        );
+        assert_eq!(
+            playlist_id_from_url("https://example.com/?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"),
+            None
+        );
+        assert_eq!(
+            playlist_id_from_url(
+                "https://notyoutube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_date_fills_in_the_month_and_day() {
+        assert_eq!(parse_date("2021").as_deref(), Some("2021-01-01T00:00:00Z"));
+        assert_eq!(
+            parse_date("2021-06").as_deref(),
+            Some("2021-06-01T00:00:00Z")
+        );
+        assert_eq!(
+            parse_date("2021-06-15").as_deref(),
+            Some("2021-06-15T00:00:00Z")
+        );
+        assert_eq!(parse_date("21-06"), None);
+        assert_eq!(parse_date("2021-6"), None);
+        assert_eq!(parse_date("2021-06-15T10:00"), None);
+    }
 }
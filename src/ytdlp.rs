@@ -0,0 +1,216 @@
+use crate::{
+    extractor::run_extractor as run,
+    youtube::{parse_date, watch_url, SearchPage, SearchQuery, SongEntry},
+};
+use serde_json::Value;
+
+const SEARCH_PAGE_SIZE: usize = 25;
+// page tokens of the Data API are opaque strings, ours are prefixed so they
+// can't be confused with them when the providers are mixed by a fallback
+const PAGE_TOKEN_PREFIX: &str = "yt-dlp:";
+
+fn song_from_json(video: &Value) -> Option<SongEntry> {
+    Some(SongEntry {
+        title: video["title"].as_str()?.to_owned(),
+        id: video["id"].as_str()?.to_owned(),
+        channel: video["channel"]
+            .as_str()
+            .or(video["uploader"].as_str())
+            .map(|channel| channel.to_owned()),
+        duration: video["duration"].as_f64().map(|secs| secs.round() as u64),
+        view_count: video["view_count"].as_u64(),
+        ..Default::default()
+    })
+}
+
+/// The `channel:` filter, with the name of the channel, its handle or its ID.
+/// The filter can't contain spaces, so they are left out of the names, and
+/// the case doesn't matter.
+fn from_channel(video: &Value, channel: &str) -> bool {
+    let normalize = |name: &str| {
+        name.trim_start_matches('@')
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .collect::<String>()
+            .to_lowercase()
+    };
+    let channel = normalize(channel);
+    ["channel", "uploader", "uploader_id", "channel_id"]
+        .iter()
+        .filter_map(|field| video[*field].as_str())
+        .any(|name| normalize(name) == channel)
+}
+
+/// The `after:` filter, `None` when yt-dlp didn't give the upload date, which
+/// is often the case with `--flat-playlist`.
+fn uploaded_after(video: &Value, date: &str) -> Option<bool> {
+    let upload_date = video["upload_date"].as_str()?;
+    // both as YYYYMMDD
+    let date = parse_date(date)?[..10].replace('-', "");
+    Some(upload_date >= date.as_str())
+}
+
+/// yt-dlp can't filter a search, so the filters are checked on the results.
+/// The results without an upload date are kept by the `after:` filter.
+fn matches_filters(video: &Value, query: &SearchQuery) -> bool {
+    let minutes = video["duration"].as_f64().map(|secs| secs / 60.0);
+    let duration_matches = match (query.duration.as_deref(), minutes) {
+        (Some("short"), Some(minutes)) => minutes < 4.0,
+        (Some("medium"), Some(minutes)) => (4.0..=20.0).contains(&minutes),
+        (Some("long"), Some(minutes)) => minutes > 20.0,
+        _ => true,
+    };
+    let is_live = matches!(
+        video["live_status"].as_str(),
+        Some("is_live") | Some("is_upcoming")
+    );
+    duration_matches
+        && query.live.is_none_or(|live| live == is_live)
+        && query
+            .channel
+            .as_deref()
+            .is_none_or(|channel| from_channel(video, channel))
+        && query
+            .published_after
+            .as_deref()
+            .is_none_or(|date| uploaded_after(video, date).unwrap_or(true))
+}
+
+/// The number of results already shown, from a page token of `search()`.
+fn page_start(page_token: Option<&str>) -> Result<usize, String> {
+    let Some(token) = page_token else {
+        return Ok(0);
+    };
+    // a token of the Data API, when the fallback takes over in the middle of
+    // a search: starting over would only show the first page again
+    token
+        .strip_prefix(PAGE_TOKEN_PREFIX)
+        .and_then(|start| start.parse::<usize>().ok())
+        .ok_or_else(|| "yt-dlp cannot continue a search of the Youtube API".to_owned())
+}
+
+/// Search with `ytsearchN:`. The page token is the number of results that were
+/// already shown, since yt-dlp has no real pagination.
+pub async fn search(input: &str, page_token: Option<&str>) -> Result<SearchPage, String> {
+    let query = SearchQuery::parse(input);
+    let start = page_start(page_token)?;
+    let end = start + SEARCH_PAGE_SIZE;
+    let output = run(&[
+        &format!("ytsearch{}:{}", end, query.keywords),
+        "--dump-json",
+        "--flat-playlist",
+        "--playlist-start",
+        &(start + 1).to_string(),
+        "--playlist-end",
+        &end.to_string(),
+    ])
+    .await?;
+    let videos = output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .collect::<Vec<Value>>();
+    // fewer results than asked means there is nothing more to find
+    let next_page_token =
+        (videos.len() == SEARCH_PAGE_SIZE).then(|| format!("{}{}", PAGE_TOKEN_PREFIX, end));
+    let songs = videos
+        .iter()
+        .filter(|video| matches_filters(video, &query))
+        .collect::<Vec<&Value>>();
+    let notice = query.published_after.as_ref().and_then(|date| {
+        songs
+            .iter()
+            .any(|video| uploaded_after(video, date).is_none())
+            .then(|| {
+                format!(
+                    "after:{} was not applied to results without an upload date",
+                    date
+                )
+            })
+    });
+    Ok(SearchPage {
+        songs: songs.into_iter().filter_map(song_from_json).collect(),
+        next_page_token,
+        notice,
+    })
+}
+
+pub async fn get_song(id: &str) -> Result<SongEntry, String> {
+    let output = run(&["--dump-json", "--skip-download", &watch_url(id)]).await?;
+    let video = serde_json::from_str::<Value>(&output).map_err(|e| e.to_string())?;
+    song_from_json(&video).ok_or_else(|| format!("yt-dlp found no video for {}", id))
+}
+
+/// Every video of a playlist. Deleted and private videos, which have no
+/// channel, are skipped like with the Data API.
+pub async fn playlist_songs(playlist_id: &str) -> Result<Vec<SongEntry>, String> {
+    let output = run(&[
+        "--dump-json",
+        "--flat-playlist",
+        &format!("https://www.youtube.com/playlist?list={}", playlist_id),
+    ])
+    .await?;
+    Ok(output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|video| song_from_json(&video))
+        .filter(|song| song.channel.is_some())
+        .collect())
+}
+
+/// yt-dlp has nothing like the related videos of the Data API, so songs with
+/// a similar title are used instead.
+pub async fn similar_songs(id: &str) -> Result<Vec<SongEntry>, String> {
+    let song = get_song(id).await?;
+    let page = search(&song.title, None).await?;
+    Ok(page
+        .songs
+        .into_iter()
+        .filter(|similar| similar.id != song.id)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn filters_are_checked_on_the_results() {
+        let video = json!({
+            "id": "dQw4w9WgXcQ",
+            "title": "Never Gonna Give You Up",
+            "channel": "Rick Astley",
+            "channel_id": "UCuAXFkgsw1L7xaCfnd5JJOw",
+            "uploader_id": "@RickAstleyYT",
+            "duration": 213.0,
+            "upload_date": "20091025",
+            "live_status": "not_live",
+        });
+        let matches = |input: &str| matches_filters(&video, &SearchQuery::parse(input));
+        assert!(matches("rick dur:short live:no"));
+        assert!(!matches("rick dur:long"));
+        assert!(!matches("rick live:yes"));
+        assert!(matches("rick channel:RickAstley"));
+        assert!(matches("rick channel:rickastley"));
+        assert!(!matches("rick channel:rick"));
+        assert!(matches("rick channel:@rickastleyyt"));
+        assert!(matches("rick channel:UCuAXFkgsw1L7xaCfnd5JJOw"));
+        assert!(!matches("rick channel:someone"));
+        assert!(matches("rick after:2009-10"));
+        assert!(!matches("rick after:2010"));
+        // without an upload date, the result is kept
+        let undated = json!({"id": "dQw4w9WgXcQ", "title": "Never Gonna Give You Up"});
+        assert!(matches_filters(
+            &undated,
+            &SearchQuery::parse("rick after:2010")
+        ));
+    }
+
+    #[test]
+    fn page_start_only_reads_its_own_tokens() {
+        assert_eq!(page_start(None), Ok(0));
+        assert_eq!(page_start(Some("yt-dlp:25")), Ok(25));
+        assert!(page_start(Some("CBkQAA")).is_err());
+        assert!(page_start(Some("yt-dlp:next")).is_err());
+    }
+}